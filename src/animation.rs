@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+// Animation is a sequence of equally sized frames of pixels, each of which is
+// an index into a palette of at most 256 colours.
+pub struct Animation {
+    width: usize,
+    height: usize,
+    palette: Vec<[u8; 3]>,
+    frames: Vec<Vec<u8>>,
+}
+
+impl Animation {
+    pub fn new(width: usize, height: usize, palette: &[[u8; 3]]) -> Result<Animation> {
+        if palette.is_empty() || palette.len() > 256 {
+            bail!("palette must have 1 to 256 colours, not {}", palette.len());
+        }
+        if width == 0 || height == 0 || width > 0xffff || height > 0xffff {
+            bail!("unsupported frame size {}x{}", width, height);
+        }
+        Ok(Animation {
+            width,
+            height,
+            palette: palette.to_vec(),
+            frames: Vec::new(),
+        })
+    }
+
+    // Adds a frame of palette indices in row-major order.
+    pub fn push(&mut self, pixels: Vec<u8>) -> Result<()> {
+        if pixels.len() != self.width * self.height {
+            bail!(
+                "frame has {} pixels, expected {}x{}",
+                pixels.len(),
+                self.width,
+                self.height
+            );
+        }
+        if let Some(&p) = pixels.iter().find(|&&p| p as usize >= self.palette.len()) {
+            bail!("pixel {} is outside the palette", p);
+        }
+        self.frames.push(pixels);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn write_ppm<W: io::Write>(&self, frame: usize, mut w: W) -> io::Result<()> {
+        write!(w, "P6\n{} {}\n255\n", self.width, self.height)?;
+        let rgb = self.frames[frame]
+            .iter()
+            .flat_map(|&p| self.palette[p as usize])
+            .collect::<Vec<_>>();
+        w.write_all(&rgb)
+    }
+
+    // Writes each frame to dir as frame_0000.ppm, frame_0001.ppm and so on.
+    pub fn write_ppms(&self, dir: &path::Path) -> Result<()> {
+        fs::create_dir_all(dir).context(format!("failed to create {:?}", dir))?;
+        for frame in 0..self.frames.len() {
+            let path = dir.join(format!("frame_{:04}.ppm", frame));
+            let file = fs::File::create(&path).context(format!("failed to create {:?}", path))?;
+            self.write_ppm(frame, io::BufWriter::new(file))
+                .context(format!("failed to write {:?}", path))?;
+        }
+        Ok(())
+    }
+
+    // Writes a looping GIF, showing each frame for delay hundredths of a
+    // second.
+    pub fn write_gif<W: io::Write>(&self, mut w: W, delay: u16) -> io::Result<()> {
+        // The colour table size must be a power of two, and LZW codes start
+        // at one bit more than the bits per pixel, with a minimum of 2.
+        let bits = (1..=8).find(|b| 1 << b >= self.palette.len()).unwrap_or(8);
+        let min_code_size = bits.max(2);
+
+        w.write_all(b"GIF89a")?;
+        w.write_all(&(self.width as u16).to_le_bytes())?;
+        w.write_all(&(self.height as u16).to_le_bytes())?;
+        w.write_all(&[0xf0 | (bits - 1), 0, 0])?;
+        for i in 0..1 << bits {
+            w.write_all(&self.palette.get(i).copied().unwrap_or_default())?;
+        }
+        w.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+        for frame in &self.frames {
+            w.write_all(&[0x21, 0xf9, 0x04, 0x00])?;
+            w.write_all(&delay.to_le_bytes())?;
+            w.write_all(&[0x00, 0x00])?;
+            w.write_all(&[0x2c, 0, 0, 0, 0])?;
+            w.write_all(&(self.width as u16).to_le_bytes())?;
+            w.write_all(&(self.height as u16).to_le_bytes())?;
+            w.write_all(&[0x00, min_code_size])?;
+            for block in lzw_encode(frame, min_code_size).chunks(255) {
+                w.write_all(&[block.len() as u8])?;
+                w.write_all(block)?;
+            }
+            w.write_all(&[0x00])?;
+        }
+        w.write_all(&[0x3b])
+    }
+}
+
+// Packs variable-width codes into bytes, least significant bit first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, width: u8) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += width;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+// Compresses pixels with the variable-width LZW used by GIF, clearing the
+// table whenever all 4096 codes are in use.
+fn lzw_encode(pixels: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut out = BitWriter {
+        bytes: Vec::new(),
+        buffer: 0,
+        bits: 0,
+    };
+    let mut table = HashMap::new();
+    let mut next_code = end + 1;
+    let mut width = min_code_size + 1;
+    out.write(clear, width);
+
+    let mut pixels = pixels.iter();
+    let mut prefix = match pixels.next() {
+        Some(&p) => p as u16,
+        None => {
+            out.write(end, width);
+            return out.finish();
+        }
+    };
+    for &p in pixels {
+        if let Some(&code) = table.get(&(prefix, p)) {
+            prefix = code;
+            continue;
+        }
+        out.write(prefix, width);
+        if next_code < 4096 {
+            table.insert((prefix, p), next_code);
+            next_code += 1;
+            if next_code > 1 << width && width < 12 {
+                width += 1;
+            }
+        } else {
+            out.write(clear, width);
+            table.clear();
+            next_code = end + 1;
+            width = min_code_size + 1;
+        }
+        prefix = p as u16;
+    }
+    out.write(prefix, width);
+    out.write(end, width);
+    out.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Decodes LZW data the way a GIF reader would.
+    fn lzw_decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut width = min_code_size + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut out = Vec::new();
+        let (mut buffer, mut bits, mut bytes) = (0u32, 0u8, data.iter());
+        loop {
+            while bits < width {
+                buffer |= (*bytes.next().unwrap() as u32) << bits;
+                bits += 8;
+            }
+            let code = (buffer & ((1 << width) - 1)) as usize;
+            buffer >>= width;
+            bits -= width;
+            if code == clear {
+                table = (0..clear).map(|p| vec![p as u8]).collect();
+                table.push(Vec::new());
+                table.push(Vec::new());
+                width = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return out;
+            }
+            let entry = match (&previous, table.get(code)) {
+                (_, Some(e)) => e.clone(),
+                (Some(prev), None) => {
+                    let mut e = prev.clone();
+                    e.push(prev[0]);
+                    e
+                }
+                (None, None) => panic!("invalid code {}", code),
+            };
+            if let Some(mut prev) = previous {
+                prev.push(entry[0]);
+                table.push(prev);
+                if table.len() == 1 << width && width < 12 {
+                    width += 1;
+                }
+            }
+            out.extend_from_slice(&entry);
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn test_lzw_round_trip() {
+        let mut state = 1u32;
+        let pixels = (0..20000)
+            .map(|i| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                if i % 3 == 0 {
+                    ((state >> 16) % 16) as u8
+                } else {
+                    (i / 700 % 16) as u8
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(pixels, lzw_decode(&lzw_encode(&pixels, 4), 4));
+        assert_eq!(vec![1u8], lzw_decode(&lzw_encode(&[1], 2), 2));
+    }
+
+    #[test]
+    fn test_write_images() {
+        let mut animation = Animation::new(2, 1, &[[0, 0, 0], [255, 255, 255]]).unwrap();
+        animation.push(vec![0, 1]).unwrap();
+        assert!(animation.push(vec![0, 2]).is_err());
+        assert!(animation.push(vec![0]).is_err());
+
+        let mut ppm = Vec::new();
+        animation.write_ppm(0, &mut ppm).unwrap();
+        assert_eq!(b"P6\n2 1\n255\n\x00\x00\x00\xff\xff\xff".to_vec(), ppm);
+
+        let mut gif = Vec::new();
+        animation.write_gif(&mut gif, 10).unwrap();
+        assert!(gif.starts_with(b"GIF89a\x02\x00\x01\x00\xf0"));
+        assert_eq!(Some(&0x3b), gif.last());
+    }
+}
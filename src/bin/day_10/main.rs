@@ -5,6 +5,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let lines = advent_2021::syntax::Lines::parse(input)?;
     println!("Part 1: {}", lines.syntax_error_score());
 
-    println!("Part 2: {}", lines.middle_completion_score());
+    let middle = lines
+        .middle_completion_score()
+        .ok_or("no incomplete lines")?;
+    println!("Part 2: {}", middle);
     Ok(())
 }
@@ -6,6 +6,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Part 1: {}", grid.step_n(100).flash_count());
 
     let grid = input.parse::<advent_2021::octopus::OctopusGrid>()?;
-    println!("Part 2: {}", grid.first_synchronized_flash());
+    let step = grid
+        .first_synchronized_flash()
+        .ok_or("the octopuses never synchronize")?;
+    println!("Part 2: {}", step);
     Ok(())
 }
@@ -60,8 +60,7 @@ impl System {
             let last_cave = &path[path.len() - 1];
             self.connections
                 .get(last_cave)
-                .or(Some(&empty))
-                .unwrap()
+                .unwrap_or(&empty)
                 .iter()
                 .filter(|cave| strategy(&path, cave))
                 .for_each(|cave| {
@@ -108,7 +107,6 @@ fn no_duplicate_small_caves(path: &[String]) -> bool {
 }
 
 #[cfg(test)]
-
 mod tests {
     use super::*;
 
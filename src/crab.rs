@@ -1,10 +1,49 @@
+use anyhow::anyhow;
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
-use rayon::iter::ParallelBridge;
+use rayon::iter::IntoParallelIterator;
 use rayon::iter::ParallelIterator;
 
 type Position = i32;
-pub struct Positions(Vec<Position>);
+type Cost = i64;
+
+// Positions of the crabs, each with a weight that multiplies its fuel cost.
+pub struct Positions {
+    positions: Vec<Position>,
+    weights: Vec<Cost>,
+}
+
+// Grouping splits the crabs between several meeting points. assignment[i] is
+// the index in points of the meeting point for crab i.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grouping {
+    pub points: Vec<Position>,
+    pub assignment: Vec<usize>,
+    pub cost: Cost,
+}
+
+// Points holds crabs moving in k dimensions; points[i] are the coordinates of
+// crab i.
+pub struct Points(Vec<Vec<Position>>);
+
+pub enum Metric {
+    Manhattan,
+    Chebyshev,
+    Euclidean,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeetingPoint {
+    pub point: Vec<f64>,
+    pub cost: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alignment {
+    pub position: Position,
+    pub cost: Cost,
+}
 
 impl std::str::FromStr for Positions {
     type Err = anyhow::Error;
@@ -17,40 +56,517 @@ impl std::str::FromStr for Positions {
                     .context(format!("failed to parse position: {}", num))
             })
             .collect::<Result<Vec<_>>>()?;
-        Ok(Positions(positions))
+        let weights = vec![1; positions.len()];
+        Ok(Positions { positions, weights })
+    }
+}
+
+// Parses whitespace-separated points, each a comma-separated group of
+// coordinates such as "1,2 3,4 5,6".
+impl std::str::FromStr for Points {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let points = s
+            .split_whitespace()
+            .map(|group| group.parse::<Positions>().map(|p| p.positions))
+            .collect::<Result<Vec<_>>>()?;
+        let dims = points
+            .first()
+            .ok_or_else(|| anyhow!("expected at least one point"))?
+            .len();
+        if let Some(p) = points.iter().find(|p| p.len() != dims) {
+            bail!("point {:?} has {} dimensions; want {}", p, p.len(), dims);
+        }
+        Ok(Points(points))
+    }
+}
+
+// FuelCost is the fuel a single crab spends to move a number of steps.
+pub trait FuelCost: Sync {
+    fn cost(&self, steps: Position) -> Cost;
+
+    // Whether the cost is convex in the number of steps. The total cost of a
+    // convex per-crab cost is convex in the meeting position, so it can be
+    // minimized by ternary search rather than scanning every position.
+    fn is_convex(&self) -> bool;
+}
+
+pub struct Linear;
+pub struct Triangular;
+pub struct Quadratic;
+
+// Capped limits another cost to a maximum, e.g. for crabs that can be towed.
+pub struct Capped<C> {
+    pub cost: C,
+    pub max: Cost,
+}
+
+// Custom wraps an arbitrary per-step cost function.
+pub struct Custom<F> {
+    f: F,
+    convex: bool,
+}
+
+impl FuelCost for Linear {
+    fn cost(&self, steps: Position) -> Cost {
+        steps as Cost
+    }
+
+    fn is_convex(&self) -> bool {
+        true
+    }
+}
+
+impl FuelCost for Triangular {
+    fn cost(&self, steps: Position) -> Cost {
+        let steps = steps as Cost;
+        steps * (steps + 1) / 2
+    }
+
+    fn is_convex(&self) -> bool {
+        true
+    }
+}
+
+impl FuelCost for Quadratic {
+    fn cost(&self, steps: Position) -> Cost {
+        let steps = steps as Cost;
+        steps * steps
+    }
+
+    fn is_convex(&self) -> bool {
+        true
+    }
+}
+
+impl<C: FuelCost> FuelCost for Capped<C> {
+    fn cost(&self, steps: Position) -> Cost {
+        self.cost.cost(steps).min(self.max)
+    }
+
+    fn is_convex(&self) -> bool {
+        false
+    }
+}
+
+impl<F: Fn(Position) -> Cost + Sync> Custom<F> {
+    pub fn new(f: F) -> Self {
+        Custom { f, convex: false }
+    }
+
+    // The caller promises that f is convex, allowing ternary search.
+    pub fn convex(f: F) -> Self {
+        Custom { f, convex: true }
+    }
+}
+
+impl<F: Fn(Position) -> Cost + Sync> FuelCost for Custom<F> {
+    fn cost(&self, steps: Position) -> Cost {
+        (self.f)(steps)
+    }
+
+    fn is_convex(&self) -> bool {
+        self.convex
     }
 }
 
 impl Positions {
-    pub fn cheapest_alignment_cost(&self) -> i32 {
-        let (min, max) = (*self.0.iter().min().unwrap(), *self.0.iter().max().unwrap());
-        (min..=max)
-            .par_bridge()
-            .map(|pos| self.0.iter().map(|val| (val - pos).abs()).sum())
-            .min()
-            .unwrap()
+    pub fn with_weights(self, weights: Vec<Cost>) -> Result<Self> {
+        if weights.len() != self.positions.len() {
+            bail!(
+                "got {} weights for {} crabs",
+                weights.len(),
+                self.positions.len()
+            );
+        }
+        if let Some(w) = weights.iter().find(|&&w| w < 0) {
+            bail!("weights must be non-negative; got {}", w);
+        }
+        Ok(Positions {
+            positions: self.positions,
+            weights,
+        })
     }
 
-    pub fn cheapest_alignment_cost_nonlinear(&self) -> i32 {
-        let (min, max) = (*self.0.iter().min().unwrap(), *self.0.iter().max().unwrap());
-        (min..=max)
-            .par_bridge()
-            .map(|pos| {
-                self.0
-                    .iter()
-                    .map(|val| {
-                        let steps = (val - pos).abs();
-                        steps * (steps + 1) / 2
-                    })
-                    .sum()
+    // Linear fuel cost is minimized at the weighted median position.
+    pub fn cheapest_alignment(&self) -> Alignment {
+        let mut sorted = self
+            .positions
+            .iter()
+            .copied()
+            .zip(self.weights.iter().copied())
+            .collect::<Vec<_>>();
+        sorted.sort_unstable();
+        let total = self.weights.iter().sum::<Cost>();
+        let mut seen = 0;
+        let position = sorted
+            .iter()
+            .find(|(_, w)| {
+                seen += w;
+                2 * seen >= total
             })
-            .min()
+            .unwrap_or(&sorted[0])
+            .0;
+        Alignment {
+            position,
+            cost: self.cost_at(position, &Linear),
+        }
+    }
+
+    // Triangular fuel cost is minimized within half a step of the weighted
+    // mean position, so only the integers around it need checking.
+    pub fn cheapest_alignment_nonlinear(&self) -> Alignment {
+        let total = self.weights.iter().sum::<Cost>().max(1);
+        let sum = self
+            .positions
+            .iter()
+            .zip(&self.weights)
+            .map(|(&p, w)| p as Cost * w)
+            .sum::<Cost>();
+        let mean = sum.div_euclid(total) as Position;
+        self.cheapest_alignment_in(mean - 1..=mean + 2, &Triangular)
+    }
+
+    pub fn cheapest_alignment_cost(&self) -> Cost {
+        self.cheapest_alignment().cost
+    }
+
+    pub fn cheapest_alignment_cost_nonlinear(&self) -> Cost {
+        self.cheapest_alignment_nonlinear().cost
+    }
+
+    // Finds the cheapest position between the outermost crabs for any cost,
+    // using ternary search when the cost is convex and scanning otherwise.
+    pub fn cheapest_alignment_with<C: FuelCost>(&self, cost: &C) -> Alignment {
+        let (mut lo, mut hi) = (
+            *self.positions.iter().min().unwrap(),
+            *self.positions.iter().max().unwrap(),
+        );
+        if cost.is_convex() {
+            while hi - lo > 2 {
+                let m1 = lo + (hi - lo) / 3;
+                let m2 = hi - (hi - lo) / 3;
+                match self.cost_at(m1, cost).cmp(&self.cost_at(m2, cost)) {
+                    std::cmp::Ordering::Less => hi = m2 - 1,
+                    std::cmp::Ordering::Greater => lo = m1 + 1,
+                    std::cmp::Ordering::Equal => {
+                        lo = m1;
+                        hi = m2;
+                    }
+                }
+            }
+        }
+        self.cheapest_alignment_in(lo..=hi, cost)
+    }
+
+    // Finds the cheapest of the allowed positions, such as docking spots.
+    pub fn cheapest_alignment_among<C: FuelCost>(
+        &self,
+        allowed: &[Position],
+        cost: &C,
+    ) -> Result<Alignment> {
+        allowed
+            .iter()
+            .map(|&position| Alignment {
+                position,
+                cost: self.cost_at(position, cost),
+            })
+            .min_by_key(|a| (a.cost, a.position))
+            .ok_or_else(|| anyhow!("no allowed positions"))
+    }
+
+    // Splits the crabs into at most k groups, each meeting at its own point,
+    // minimizing the total linear fuel cost. Groups in an optimal solution are
+    // contiguous runs of sorted positions, so this is a dynamic program over
+    // the sorted crabs.
+    pub fn cheapest_grouping(&self, k: usize) -> Result<Grouping> {
+        if k == 0 {
+            bail!("need at least one meeting point");
+        }
+        let mut order = (0..self.positions.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| self.positions[i]);
+        let n = order.len();
+        let k = k.min(n);
+        let (mut w, mut wx) = (vec![0; n + 1], vec![0; n + 1]);
+        for (t, &i) in order.iter().enumerate() {
+            w[t + 1] = w[t] + self.weights[i];
+            wx[t + 1] = wx[t] + self.weights[i] * self.positions[i] as Cost;
+        }
+        // The weighted median of sorted crabs i..j and the cost of meeting there.
+        let group = |i: usize, j: usize| {
+            let (mut m, mut hi) = (i, j - 1);
+            while m < hi {
+                let mid = (m + hi) / 2;
+                if 2 * (w[mid + 1] - w[i]) >= w[j] - w[i] {
+                    hi = mid;
+                } else {
+                    m = mid + 1;
+                }
+            }
+            let x = self.positions[order[m]] as Cost;
+            let cost = x * (w[m] - w[i]) - (wx[m] - wx[i]) + (wx[j] - wx[m]) - x * (w[j] - w[m]);
+            (x as Position, cost)
+        };
+
+        // best[g][j] is the cheapest way to split the first j crabs into g
+        // groups, and split[g][j] is where the last of those groups starts.
+        let mut best = vec![vec![Cost::MAX; n + 1]; k + 1];
+        let mut split = vec![vec![0; n + 1]; k + 1];
+        best[0][0] = 0;
+        for g in 1..=k {
+            for j in g..=n {
+                for i in g - 1..j {
+                    if best[g - 1][i] == Cost::MAX {
+                        continue;
+                    }
+                    let cost = best[g - 1][i] + group(i, j).1;
+                    if cost < best[g][j] {
+                        best[g][j] = cost;
+                        split[g][j] = i;
+                    }
+                }
+            }
+        }
+
+        let mut points = vec![0; k];
+        let mut assignment = vec![0; n];
+        let mut j = n;
+        for g in (1..=k).rev() {
+            let i = split[g][j];
+            points[g - 1] = group(i, j).0;
+            order[i..j].iter().for_each(|&c| assignment[c] = g - 1);
+            j = i;
+        }
+        Ok(Grouping {
+            points,
+            assignment,
+            cost: best[k][n],
+        })
+    }
+
+    fn cheapest_alignment_in<C: FuelCost>(
+        &self,
+        range: std::ops::RangeInclusive<Position>,
+        cost: &C,
+    ) -> Alignment {
+        range
+            .into_par_iter()
+            .map(|position| Alignment {
+                position,
+                cost: self.cost_at(position, cost),
+            })
+            .min_by_key(|a| (a.cost, a.position))
             .unwrap()
     }
+
+    fn cost_at<C: FuelCost>(&self, position: Position, cost: &C) -> Cost {
+        self.positions
+            .iter()
+            .zip(&self.weights)
+            .map(|(val, w)| w * cost.cost((val - position).abs()))
+            .sum()
+    }
 }
 
-#[cfg(test)]
+impl Points {
+    pub fn dimensions(&self) -> usize {
+        self.0[0].len()
+    }
 
+    pub fn cheapest_meeting_point(&self, metric: Metric) -> MeetingPoint {
+        let point = match metric {
+            // Manhattan distance separates by axis, so each coordinate is the
+            // median along its axis.
+            Metric::Manhattan => (0..self.dimensions())
+                .map(|axis| self.axis(axis).cheapest_alignment().position as f64)
+                .collect(),
+            Metric::Chebyshev => self.chebyshev_center(),
+            Metric::Euclidean => self.geometric_median(),
+        };
+        MeetingPoint {
+            cost: self.cost_at(&point, &metric),
+            point,
+        }
+    }
+
+    fn axis(&self, axis: usize) -> Positions {
+        let positions = self.0.iter().map(|p| p[axis]).collect::<Vec<_>>();
+        let weights = vec![1; positions.len()];
+        Positions { positions, weights }
+    }
+
+    fn cost_at(&self, point: &[f64], metric: &Metric) -> f64 {
+        self.0
+            .iter()
+            .map(|p| {
+                let deltas = p.iter().zip(point).map(|(&a, b)| (a as f64 - b).abs());
+                match metric {
+                    Metric::Manhattan => deltas.sum(),
+                    Metric::Chebyshev => deltas.fold(0.0, f64::max),
+                    Metric::Euclidean => deltas.map(|d| d * d).sum::<f64>().sqrt(),
+                }
+            })
+            .sum()
+    }
+
+    // Chebyshev cost is piecewise linear, so the optimum solves a linear
+    // program: minimize the sum of t_i subject to t_i >= |x_d - p_id| for
+    // each crab i and axis d. Measuring from the lowest coordinate on each
+    // axis, with q_id = p_id - lo_d and y_d = x_d - lo_d, its dual is
+    //
+    //   maximize   sum of q_id * (a_id - b_id)
+    //   subject to sum over d of (a_id + b_id) <= 1 for each crab i
+    //              sum over i of (a_id - b_id) <= 0 for each axis d
+    //
+    // which is feasible at zero, so the simplex method can start there. The
+    // optimal t and y are the final reduced costs of the dual's slacks.
+    fn chebyshev_center(&self) -> Vec<f64> {
+        let (n, k) = (self.0.len(), self.dimensions());
+        let lo = (0..k)
+            .map(|d| self.0.iter().map(|p| p[d]).min().unwrap())
+            .collect::<Vec<_>>();
+        // Columns are a_id, then b_id, then one slack per row.
+        let (a, b, slack) = (0, n * k, 2 * n * k);
+        let mut rows = vec![vec![0.0; slack + n + k + 1]; n + k];
+        let mut objective = vec![0.0; slack + n + k + 1];
+        for (i, p) in self.0.iter().enumerate() {
+            for d in 0..k {
+                let q = (p[d] - lo[d]) as f64;
+                let id = i * k + d;
+                objective[a + id] = -q;
+                objective[b + id] = q;
+                rows[i][a + id] = 1.0;
+                rows[i][b + id] = 1.0;
+                rows[n + d][a + id] = 1.0;
+                rows[n + d][b + id] = -1.0;
+            }
+        }
+        for (r, row) in rows.iter_mut().enumerate() {
+            row[slack + r] = 1.0;
+            *row.last_mut().unwrap() = if r < n { 1.0 } else { 0.0 };
+        }
+        let basis = (slack..slack + n + k).collect();
+        maximize(&mut rows, &mut objective, basis);
+        (0..k)
+            .map(|d| lo[d] as f64 + objective[slack + n + d])
+            .collect()
+    }
+
+    // Weiszfeld's algorithm: repeatedly move to the average of the points
+    // weighted by the inverse of their distance from the current estimate.
+    // Vardi and Zhang's modification handles estimates that land on a crab.
+    fn geometric_median(&self) -> Vec<f64> {
+        let n = self.0.len() as f64;
+        let mut estimate = (0..self.dimensions())
+            .map(|axis| self.0.iter().map(|p| p[axis] as f64).sum::<f64>() / n)
+            .collect::<Vec<_>>();
+        for _ in 0..10_000 {
+            let mut weighted = vec![0.0; estimate.len()];
+            let mut pull = vec![0.0; estimate.len()];
+            let mut total_weight = 0.0;
+            let mut coincident = 0.0;
+            for p in &self.0 {
+                let dist = p
+                    .iter()
+                    .zip(&estimate)
+                    .map(|(&a, b)| (a as f64 - b).powi(2))
+                    .sum::<f64>()
+                    .sqrt();
+                if dist < 1e-12 {
+                    coincident += 1.0;
+                    continue;
+                }
+                for ((w, r), (&a, e)) in weighted
+                    .iter_mut()
+                    .zip(pull.iter_mut())
+                    .zip(p.iter().zip(&estimate))
+                {
+                    *w += a as f64 / dist;
+                    *r += (a as f64 - e) / dist;
+                }
+                total_weight += 1.0 / dist;
+            }
+            if total_weight == 0.0 {
+                break;
+            }
+            let pull = pull.iter().map(|r| r * r).sum::<f64>().sqrt();
+            let stay = if coincident > 0.0 {
+                (coincident / pull).min(1.0)
+            } else {
+                0.0
+            };
+            let next = weighted
+                .iter()
+                .zip(&estimate)
+                .map(|(w, e)| (1.0 - stay) * w / total_weight + stay * e)
+                .collect::<Vec<_>>();
+            let shift = next
+                .iter()
+                .zip(&estimate)
+                .map(|(a, b)| (a - b).powi(2))
+                .sum::<f64>()
+                .sqrt();
+            estimate = next;
+            if shift < 1e-10 {
+                break;
+            }
+        }
+        estimate
+    }
+}
+
+// Runs the simplex method on a tableau in which each row holds its
+// coefficients followed by its right-hand side, starting from the given
+// feasible basis. The objective row holds the negated objective, so it ends
+// up holding the reduced costs and the objective's value. Bland's rule picks
+// the lowest eligible column and row, which avoids cycling on the degenerate
+// pivots these programs are full of.
+fn maximize(rows: &mut [Vec<f64>], objective: &mut [f64], mut basis: Vec<usize>) {
+    const EPSILON: f64 = 1e-9;
+    let rhs = objective.len() - 1;
+    // Reduced costs scale with the objective's coefficients.
+    let scale = objective.iter().fold(1f64, |m, c| m.max(c.abs()));
+    while let Some(column) = (0..rhs).find(|&c| objective[c] < -EPSILON * scale) {
+        let mut pivot: Option<(usize, f64)> = None;
+        for (r, row) in rows.iter().enumerate() {
+            if row[column] > EPSILON {
+                let ratio = row[rhs] / row[column];
+                let better = match pivot {
+                    None => true,
+                    Some((p, best)) => {
+                        ratio < best - EPSILON || (ratio <= best + EPSILON && basis[r] < basis[p])
+                    }
+                };
+                if better {
+                    pivot = Some((r, ratio));
+                }
+            }
+        }
+        // The programs solved here are bounded.
+        let r = pivot.expect("unbounded linear program").0;
+        let divisor = rows[r][column];
+        rows[r].iter_mut().for_each(|v| *v /= divisor);
+        let pivot_row = rows[r].clone();
+        let eliminate = |row: &mut [f64]| {
+            let factor = row[column];
+            if factor != 0.0 {
+                row.iter_mut()
+                    .zip(&pivot_row)
+                    .for_each(|(v, p)| *v -= factor * p);
+            }
+        };
+        for (other, row) in rows.iter_mut().enumerate() {
+            if other != r {
+                eliminate(row);
+            }
+        }
+        eliminate(objective);
+        basis[r] = column;
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
 
@@ -67,4 +583,233 @@ mod tests {
         let positions = INPUT.parse::<Positions>().unwrap();
         assert_eq!(168, positions.cheapest_alignment_cost_nonlinear());
     }
+
+    #[test]
+    fn test_cheapest_alignment() {
+        let positions = INPUT.parse::<Positions>().unwrap();
+        assert_eq!(
+            Alignment {
+                position: 2,
+                cost: 37
+            },
+            positions.cheapest_alignment()
+        );
+        assert_eq!(
+            Alignment {
+                position: 5,
+                cost: 168
+            },
+            positions.cheapest_alignment_nonlinear()
+        );
+    }
+
+    #[test]
+    fn test_cheapest_alignment_matches_brute_force() {
+        for input in [
+            INPUT,
+            "0",
+            "1,2",
+            "5,5,5,-3",
+            "0,0,0,0,100",
+            "-7,-2,9,40,41,3",
+        ] {
+            let positions = input.parse::<Positions>().unwrap();
+            assert_eq!(
+                brute_force(&positions, &Linear),
+                positions.cheapest_alignment_cost(),
+                "{}",
+                input
+            );
+            assert_eq!(
+                brute_force(&positions, &Triangular),
+                positions.cheapest_alignment_cost_nonlinear(),
+                "{}",
+                input
+            );
+            assert_eq!(
+                brute_force(&positions, &Quadratic),
+                positions.cheapest_alignment_with(&Quadratic).cost,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_cheapest_alignment_with() {
+        let positions = INPUT.parse::<Positions>().unwrap();
+        assert_eq!(37, positions.cheapest_alignment_with(&Linear).cost);
+        assert_eq!(168, positions.cheapest_alignment_with(&Triangular).cost);
+
+        let capped = Capped {
+            cost: Triangular,
+            max: 10,
+        };
+        assert_eq!(
+            brute_force(&positions, &capped),
+            positions.cheapest_alignment_with(&capped).cost
+        );
+        let custom = Custom::new(|steps| if steps % 2 == 0 { 0 } else { 1 });
+        assert_eq!(
+            Alignment {
+                position: 0,
+                cost: 3
+            },
+            positions.cheapest_alignment_with(&custom)
+        );
+    }
+
+    #[test]
+    fn test_weights() {
+        let positions = "0,10".parse::<Positions>().unwrap();
+        let positions = positions.with_weights(vec![1, 3]).unwrap();
+        let want = Alignment {
+            position: 10,
+            cost: 10,
+        };
+        assert_eq!(want, positions.cheapest_alignment());
+        assert_eq!(want, positions.cheapest_alignment_with(&Linear));
+        assert_eq!(
+            positions.cheapest_alignment_with(&Triangular),
+            positions.cheapest_alignment_nonlinear()
+        );
+
+        let positions = "0,10".parse::<Positions>().unwrap();
+        assert!(positions.with_weights(vec![1]).is_err());
+    }
+
+    fn brute_force<C: FuelCost>(positions: &Positions, cost: &C) -> Cost {
+        let (min, max) = (
+            *positions.positions.iter().min().unwrap(),
+            *positions.positions.iter().max().unwrap(),
+        );
+        (min..=max)
+            .map(|pos| positions.cost_at(pos, cost))
+            .min()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_cheapest_meeting_point() {
+        let points = "0,0 4,0 0,4 4,4 1,1".parse::<Points>().unwrap();
+        assert_eq!(2, points.dimensions());
+
+        let manhattan = points.cheapest_meeting_point(Metric::Manhattan);
+        assert_eq!(vec![1.0, 1.0], manhattan.point);
+        assert_eq!(16.0, manhattan.cost);
+
+        let chebyshev = points.cheapest_meeting_point(Metric::Chebyshev);
+        let brute = (0..=40)
+            .flat_map(|x| (0..=40).map(move |y| [x as f64 / 10.0, y as f64 / 10.0]))
+            .map(|p| points.cost_at(&p, &Metric::Chebyshev))
+            .fold(f64::INFINITY, f64::min);
+        assert!((chebyshev.cost - brute).abs() < 1e-4, "{:?}", chebyshev);
+
+        let euclidean = points.cheapest_meeting_point(Metric::Euclidean);
+        for nearby in [[1.0, 1.0], [1.5, 1.5], [2.0, 2.0], [1.4, 1.2]] {
+            assert!(
+                euclidean.cost <= points.cost_at(&nearby, &Metric::Euclidean) + 1e-6,
+                "{:?}",
+                euclidean
+            );
+        }
+        assert!((euclidean.point[0] - euclidean.point[1]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_cheapest_meeting_point_3d() {
+        let points = "0,0,0 2,0,0 0,2,0 0,0,2".parse::<Points>().unwrap();
+        let manhattan = points.cheapest_meeting_point(Metric::Manhattan);
+        assert_eq!(vec![0.0, 0.0, 0.0], manhattan.point);
+        assert_eq!(6.0, manhattan.cost);
+
+        let chebyshev = points.cheapest_meeting_point(Metric::Chebyshev);
+        let brute = (0..=20)
+            .flat_map(|x| (0..=20).flat_map(move |y| (0..=20).map(move |z| [x, y, z])))
+            .map(|p| points.cost_at(&p.map(|c| c as f64 / 10.0), &Metric::Chebyshev))
+            .fold(f64::INFINITY, f64::min);
+        assert!(
+            chebyshev.cost <= brute + 1e-4,
+            "{:?} vs {}",
+            chebyshev,
+            brute
+        );
+    }
+
+    #[test]
+    fn test_cheapest_meeting_point_high_dimensions() {
+        for k in [4, 5] {
+            let mut state = 0x2545f4914f6cdd1du64;
+            let mut coordinate = || {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state % 1000) as i32
+            };
+            let points = Points(
+                (0..50)
+                    .map(|_| (0..k).map(|_| coordinate()).collect())
+                    .collect(),
+            );
+            let start = std::time::Instant::now();
+            let chebyshev = points.cheapest_meeting_point(Metric::Chebyshev);
+            assert!(start.elapsed().as_secs() < 2, "{:?}", start.elapsed());
+
+            // No step along an axis or diagonal does better.
+            for direction in 0..3usize.pow(k as u32) {
+                let mut nearby = chebyshev.point.clone();
+                let mut rest = direction;
+                for c in &mut nearby {
+                    *c += (rest % 3) as f64 - 1.0;
+                    rest /= 3;
+                }
+                let cost = points.cost_at(&nearby, &Metric::Chebyshev);
+                assert!(chebyshev.cost <= cost + 1e-6, "{:?} vs {}", chebyshev, cost);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_points() {
+        assert!("1,2 3".parse::<Points>().is_err());
+        assert!("".parse::<Points>().is_err());
+        assert!("1,x".parse::<Points>().is_err());
+    }
+
+    #[test]
+    fn test_cheapest_grouping() {
+        let positions = INPUT.parse::<Positions>().unwrap();
+        let single = positions.cheapest_grouping(1).unwrap();
+        assert_eq!(vec![2], single.points);
+        assert_eq!(37, single.cost);
+
+        let pair = positions.cheapest_grouping(2).unwrap();
+        assert_eq!(
+            Grouping {
+                points: vec![2, 14],
+                assignment: vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+                cost: 13,
+            },
+            pair
+        );
+
+        let all = positions.cheapest_grouping(20).unwrap();
+        assert_eq!(0, all.cost);
+        assert!(positions.cheapest_grouping(0).is_err());
+    }
+
+    #[test]
+    fn test_cheapest_alignment_among() {
+        let positions = INPUT.parse::<Positions>().unwrap();
+        assert_eq!(
+            Alignment {
+                position: 5,
+                cost: 45
+            },
+            positions
+                .cheapest_alignment_among(&[-10, 5, 16], &Linear)
+                .unwrap()
+        );
+        assert!(positions.cheapest_alignment_among(&[], &Linear).is_err());
+    }
 }
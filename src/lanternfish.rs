@@ -1,27 +1,47 @@
 use std::collections::VecDeque;
+use std::io;
+use std::ops::{Add, Mul};
 
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 
 #[derive(Default, Clone)]
 pub struct School(VecDeque<usize>);
 
+// Schools holds several independent schools, one per input line.
+pub struct Schools(Vec<School>);
+
 impl std::str::FromStr for School {
     type Err = anyhow::Error;
 
+    // Accepts a comma-separated list of fish ages, or of "age:count" buckets,
+    // or a mix of both.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut school = School::default();
-        let ages = s
-            .split(',')
-            .map(|num| {
-                num.parse::<i32>()
-                    .context(format!("failed to parse age: {}", num))
-            })
-            .collect::<Result<Vec<_>>>()?;
-        (0..=8).for_each(|i| {
-            school.0.push_back(ages.iter().filter(|&&x| x == i).count());
-        });
-        Ok(school)
+        let mut ages = [0usize; AGES];
+        for token in s.trim().split(',').map(str::trim) {
+            let (age, count) = match token.split_once(':') {
+                Some((age, count)) => (
+                    age.trim(),
+                    count
+                        .trim()
+                        .parse::<usize>()
+                        .context(format!("failed to parse count in: {:?}", token))?,
+                ),
+                None => (token, 1),
+            };
+            let age = age
+                .parse::<usize>()
+                .context(format!("failed to parse age: {:?}", token))?;
+            if age >= AGES {
+                bail!("age out of range 0..={}: {:?}", AGES - 1, token);
+            }
+            ages[age] = match ages[age].checked_add(count) {
+                Some(total) => total,
+                None => bail!("too many fish of age {}: {:?}", age, token),
+            };
+        }
+        Ok(School(ages.into_iter().collect()))
     }
 }
 
@@ -38,11 +58,229 @@ impl School {
     pub fn count(&self) -> usize {
         self.0.iter().sum()
     }
+
+    // Returns the number of fish of each age after n days, computed by raising
+    // the daily transition matrix to the n-th power. T is typically u128,
+    // num_bigint::BigUint or modular::Modular for counts that would overflow.
+    pub fn project<T>(&self, n: u64) -> Vec<T>
+    where
+        T: Clone + From<u64> + Add<Output = T> + Mul<Output = T>,
+    {
+        let transition = Matrix::<T>::transition().pow(n);
+        let ages = self
+            .0
+            .iter()
+            .map(|&c| T::from(c as u64))
+            .collect::<Vec<_>>();
+        transition.apply(&ages)
+    }
+
+    pub fn project_count<T>(&self, n: u64) -> T
+    where
+        T: Clone + From<u64> + Add<Output = T> + Mul<Output = T>,
+    {
+        self.project::<T>(n)
+            .into_iter()
+            .fold(T::from(0), |acc, c| acc + c)
+    }
+}
+
+const AGES: usize = 9;
+
+impl Schools {
+    pub fn parse<T: io::BufRead>(r: T) -> Result<Schools> {
+        let schools = r
+            .lines()
+            .filter(|l| !matches!(l, Ok(l) if l.trim().is_empty()))
+            .enumerate()
+            .map(|(i, l)| {
+                l.context("failed to read line")?
+                    .parse::<School>()
+                    .context(format!("failed to parse school {}", i + 1))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Schools(schools))
+    }
+
+    pub fn simulate_n_days(self, n: usize) -> Schools {
+        Schools(
+            self.0
+                .into_iter()
+                .map(|school| school.simulate_n_days(n))
+                .collect(),
+        )
+    }
+
+    pub fn counts(&self) -> Vec<usize> {
+        self.0.iter().map(School::count).collect()
+    }
+}
+
+// History records the number of fish of each age at the start of every day,
+// with day 0 being the initial school.
+pub struct History(Vec<[usize; AGES]>);
+
+impl School {
+    pub fn history(&self, n: usize) -> History {
+        let mut school = self.clone();
+        let mut days = Vec::with_capacity(n + 1);
+        for day in 0..=n {
+            if day > 0 {
+                school = school.simulate_n_days(1);
+            }
+            let mut ages = [0usize; AGES];
+            ages.iter_mut()
+                .zip(school.0.iter())
+                .for_each(|(a, &c)| *a = c);
+            days.push(ages);
+        }
+        History(days)
+    }
+
+    // Returns the factor the population is multiplied by each day in the long
+    // run: the dominant eigenvalue of the transition matrix, found by power
+    // iteration.
+    pub fn growth_factor() -> f64 {
+        let mut ages = [1.0; AGES];
+        let mut factor = 1.0;
+        for _ in 0..1000 {
+            let mut next = [0.0; AGES];
+            next[..AGES - 1].copy_from_slice(&ages[1..]);
+            next[6] += ages[0];
+            next[8] = ages[0];
+            factor = next.iter().sum::<f64>() / ages.iter().sum::<f64>();
+            ages = next.map(|a| a / factor);
+        }
+        factor
+    }
+
+    pub fn asymptotic_doubling_time() -> f64 {
+        2f64.ln() / School::growth_factor().ln()
+    }
+}
+
+impl History {
+    pub fn buckets(&self) -> &[[usize; AGES]] {
+        &self.0
+    }
+
+    pub fn totals(&self) -> Vec<usize> {
+        self.0.iter().map(|ages| ages.iter().sum()).collect()
+    }
+
+    // Returns the ratio of each day's population to the previous day's,
+    // starting with day 1.
+    pub fn growth_rates(&self) -> Vec<f64> {
+        self.totals()
+            .windows(2)
+            .map(|w| w[1] as f64 / w[0] as f64)
+            .collect()
+    }
+
+    // Returns the first day on which the population is at least double the
+    // initial population, if that happens within the recorded history.
+    pub fn doubling_time(&self) -> Option<usize> {
+        let totals = self.totals();
+        let initial = *totals.first()?;
+        totals.iter().position(|&t| t >= 2 * initial)
+    }
+
+    pub fn write_csv<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+        write!(w, "day")?;
+        for age in 0..AGES {
+            write!(w, ",age_{}", age)?;
+        }
+        writeln!(w, ",total,growth_rate")?;
+        let rates = self.growth_rates();
+        for (day, (ages, total)) in self.0.iter().zip(self.totals()).enumerate() {
+            write!(w, "{}", day)?;
+            for count in ages {
+                write!(w, ",{}", count)?;
+            }
+            write!(w, ",{},", total)?;
+            if day > 0 {
+                write!(w, "{}", rates[day - 1])?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+}
+
+// Square matrix over the ages of the school, stored row-major.
+#[derive(Clone)]
+struct Matrix<T>(Vec<T>);
+
+impl<T> Matrix<T>
+where
+    T: Clone + From<u64> + Add<Output = T> + Mul<Output = T>,
+{
+    fn identity() -> Self {
+        Matrix(
+            (0..AGES * AGES)
+                .map(|i| T::from((i / AGES == i % AGES) as u64))
+                .collect(),
+        )
+    }
+
+    // Row i of the transition matrix lists how many fish of age i tomorrow come
+    // from each age today: everyone ages by one, and age 0 both resets to 6 and
+    // spawns a new fish at 8.
+    fn transition() -> Self {
+        Matrix(
+            (0..AGES * AGES)
+                .map(|i| {
+                    let (to, from) = (i / AGES, i % AGES);
+                    let spawned = from == 0 && (to == 6 || to == 8);
+                    T::from((from == to + 1 || spawned) as u64)
+                })
+                .collect(),
+        )
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        Matrix(
+            (0..AGES * AGES)
+                .map(|i| {
+                    let (r, c) = (i / AGES, i % AGES);
+                    (0..AGES).fold(T::from(0), |acc, k| {
+                        acc + self.0[r * AGES + k].clone() * other.0[k * AGES + c].clone()
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    fn pow(&self, mut n: u64) -> Self {
+        let mut result = Matrix::identity();
+        let mut base = self.clone();
+        while n > 0 {
+            if n & 1 == 1 {
+                result = result.mul(&base);
+            }
+            base = base.mul(&base);
+            n >>= 1;
+        }
+        result
+    }
+
+    fn apply(&self, v: &[T]) -> Vec<T> {
+        (0..AGES)
+            .map(|r| {
+                (0..AGES).fold(T::from(0), |acc, k| {
+                    acc + self.0[r * AGES + k].clone() * v[k].clone()
+                })
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::modular::Modular;
+    use crate::testutil;
+    use num_bigint::BigUint;
 
     const INPUT: &str = r"3,4,3,1,2";
 
@@ -55,4 +293,104 @@ mod tests {
         school = school.simulate_n_days(256 - 80);
         assert_eq!(26984457539, school.count());
     }
+
+    #[test]
+    fn test_parse() {
+        let school = "3,4,3,1,2\n".parse::<School>().unwrap();
+        assert_eq!(vec![0, 1, 1, 2, 1, 0, 0, 0, 0], Vec::from(school.0));
+
+        let school = " 1:1, 2:1 ,3:2,4 ".parse::<School>().unwrap();
+        assert_eq!(vec![0, 1, 1, 2, 1, 0, 0, 0, 0], Vec::from(school.0));
+
+        for (input, want) in [
+            ("3,9,1", "age out of range 0..=8: \"9\""),
+            ("3,-1", "failed to parse age: \"-1\""),
+            ("3,,1", "failed to parse age: \"\""),
+            ("3:x", "failed to parse count in: \"3:x\""),
+            (
+                "0:18446744073709551615,0:1",
+                "too many fish of age 0: \"0:1\"",
+            ),
+        ] {
+            let err = input.parse::<School>().err().unwrap();
+            assert_eq!(want, err.to_string());
+        }
+    }
+
+    #[test]
+    fn test_schools() {
+        let input = testutil::string_reader("3,4,3,1,2\n\n0:2,6:1\n");
+        let schools = Schools::parse(input).unwrap();
+        assert_eq!(vec![5, 3], schools.counts());
+        assert_eq!(vec![5934, 3747], schools.simulate_n_days(80).counts());
+    }
+
+    #[test]
+    fn test_project_count() {
+        let school = INPUT.parse::<School>().unwrap();
+        assert_eq!(26, school.project_count::<u64>(18));
+        assert_eq!(5934, school.project_count::<u64>(80));
+        assert_eq!(26984457539, school.project_count::<u128>(256));
+        assert_eq!(
+            BigUint::from(26984457539u64),
+            school.project_count::<BigUint>(256)
+        );
+
+        let simulated = school.clone().simulate_n_days(256).count() as u64;
+        type M = Modular<1_000_000_007>;
+        assert_eq!(M::from(simulated), school.project_count::<M>(256));
+    }
+
+    #[test]
+    fn test_project_count_far_horizon() {
+        let school = INPUT.parse::<School>().unwrap();
+        type M = Modular<1_000_000_007>;
+        let n = 1_000_000_000_000;
+        let direct = school.project_count::<M>(n);
+        let stepped = school
+            .project::<M>(n - 256)
+            .into_iter()
+            .enumerate()
+            .map(|(age, c)| c * single_fish(age).project_count::<M>(256))
+            .fold(M::from(0), |acc, c| acc + c);
+        assert_eq!(direct, stepped);
+    }
+
+    #[test]
+    fn test_history() {
+        let school = INPUT.parse::<School>().unwrap();
+        let history = school.history(18);
+        let totals = history.totals();
+        assert_eq!(19, totals.len());
+        assert_eq!(5, totals[0]);
+        assert_eq!(26, totals[18]);
+        assert_eq!([0, 1, 1, 2, 1, 0, 0, 0, 0], history.buckets()[0]);
+        assert_eq!(Some(5), history.doubling_time());
+        assert_eq!(1.2, history.growth_rates()[1]);
+
+        let factor = School::growth_factor();
+        assert!((factor - 1.09102).abs() < 1e-5, "got {}", factor);
+        assert!((School::asymptotic_doubling_time() - 7.9).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let school = INPUT.parse::<School>().unwrap();
+        let mut csv = Vec::new();
+        school.history(2).write_csv(&mut csv).unwrap();
+        assert_eq!(
+            "day,age_0,age_1,age_2,age_3,age_4,age_5,age_6,age_7,age_8,total,growth_rate
+0,0,1,1,2,1,0,0,0,0,5,
+1,1,1,2,1,0,0,0,0,0,5,1
+2,1,2,1,0,0,0,1,0,1,6,1.2
+",
+            String::from_utf8(csv).unwrap()
+        );
+    }
+
+    fn single_fish(age: usize) -> School {
+        let mut school = School::default();
+        (0..=8).for_each(|i| school.0.push_back((i == age) as usize));
+        school
+    }
 }
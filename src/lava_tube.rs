@@ -1,11 +1,79 @@
 use anyhow::anyhow;
+use anyhow::bail;
 use anyhow::Result;
 use itertools::Itertools;
-use std::collections::{HashSet, VecDeque};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::ops::RangeInclusive;
 
 type Height = u8;
+type Cost = u64;
 pub struct HeightMap(array2d::Array2D<Height>);
 
+// Basin is a region of the map. Its perimeter is the number of cell edges
+// between it and other basins, walls or the edge of the map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Basin {
+    pub size: usize,
+    pub low_point: (usize, usize),
+    pub top_left: (usize, usize),
+    pub bottom_right: (usize, usize),
+    pub perimeter: usize,
+}
+
+// Basins labels each cell of a map with the index of its basin, or None for a
+// wall.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Basins {
+    labels: array2d::Array2D<Option<usize>>,
+    basins: Vec<Basin>,
+}
+
+// BasinRules decide how a map splits into basins: which heights are walls,
+// whether cells touching diagonally are connected, and whether a flat region
+// with no lower neighbour counts as one low area.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BasinRules {
+    wall: Height,
+    diagonals: bool,
+    plateaus: bool,
+}
+
+const ORTHOGONAL: [(i64, i64); 4] = [(0, 1), (0, -1), (1, 0), (-1, 0)];
+const ALL_DIRECTIONS: [(i64, i64); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+
+// DisjointSet is a union-find forest over the integers 0..n.
+struct DisjointSet {
+    parents: Vec<usize>,
+    sizes: Vec<usize>,
+}
+
+// CostModel gives the cost of moving onto a cell: a base cost, plus a cost
+// per unit of the cell's height, plus a penalty per unit climbed to reach it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CostModel {
+    pub base: Cost,
+    pub per_height: Cost,
+    pub per_climb: Cost,
+}
+
+// Path is a route between two cells, including both ends. Its cost doesn't
+// include the starting cell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path {
+    pub cells: Vec<(usize, usize)>,
+    pub cost: Cost,
+}
+
 impl std::str::FromStr for HeightMap {
     type Err = anyhow::Error;
 
@@ -18,8 +86,7 @@ impl std::str::FromStr for HeightMap {
         let height = s.lines().count();
         let elems = s
             .lines()
-            .map(|l| l.chars().map(|c| c as u8 - b'0').collect::<Vec<_>>())
-            .flatten()
+            .flat_map(|l| l.chars().map(|c| c as u8 - b'0').collect::<Vec<_>>())
             .collect::<Vec<_>>();
         let arr = array2d::Array2D::from_row_major(&elems, height, width);
         Ok(HeightMap(arr))
@@ -38,7 +105,259 @@ impl std::fmt::Display for HeightMap {
     }
 }
 
+impl Default for BasinRules {
+    fn default() -> Self {
+        BasinRules {
+            wall: 9,
+            diagonals: false,
+            plateaus: false,
+        }
+    }
+}
+
+impl BasinRules {
+    // Makes cells of this height or higher walls.
+    pub fn walls_from(mut self, wall: Height) -> Self {
+        self.wall = wall;
+        self
+    }
+
+    pub fn diagonals(mut self, diagonals: bool) -> Self {
+        self.diagonals = diagonals;
+        self
+    }
+
+    pub fn plateaus(mut self, plateaus: bool) -> Self {
+        self.plateaus = plateaus;
+        self
+    }
+
+    fn is_wall(&self, h: Height) -> bool {
+        h >= self.wall
+    }
+
+    fn offsets(&self) -> &'static [(i64, i64)] {
+        if self.diagonals {
+            &ALL_DIRECTIONS
+        } else {
+            &ORTHOGONAL
+        }
+    }
+}
+
+impl DisjointSet {
+    fn new(n: usize) -> Self {
+        DisjointSet {
+            parents: (0..n).collect(),
+            sizes: vec![1; n],
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.sizes[a] < self.sizes[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parents[b] = a;
+        self.sizes[a] += self.sizes[b];
+    }
+}
+
+impl Basins {
+    pub fn label(&self, c: (usize, usize)) -> Option<usize> {
+        self.labels[c]
+    }
+
+    pub fn labels(&self) -> &array2d::Array2D<Option<usize>> {
+        &self.labels
+    }
+
+    pub fn basins(&self) -> &[Basin] {
+        &self.basins
+    }
+}
+
+impl Default for CostModel {
+    // The cost of a move is the height of the cell moved onto.
+    fn default() -> Self {
+        CostModel {
+            base: 0,
+            per_height: 1,
+            per_climb: 0,
+        }
+    }
+}
+
+impl CostModel {
+    fn step(&self, from: Height, to: Height) -> Cost {
+        self.base + self.per_height * to as Cost + self.per_climb * to.saturating_sub(from) as Cost
+    }
+}
+
+impl Path {
+    // Draws the map with the cells of the path replaced by '#'.
+    pub fn render(&self, map: &HeightMap) -> String {
+        let on_path = self.cells.iter().collect::<HashSet<_>>();
+        let mut out = String::new();
+        for r in 0..map.0.num_rows() {
+            for c in 0..map.0.num_columns() {
+                if on_path.contains(&(r, c)) {
+                    out.push('#');
+                } else {
+                    out.push((map.0[(r, c)] + b'0') as char);
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
 impl HeightMap {
+    // Returns the map repeated n times in each direction. Each tile is one
+    // higher than the tile above or to its left, and heights that go past the
+    // end of the range wrap around to its start. Heights are drawn as single
+    // digits, so the range must lie within 0..=9 and contain every height.
+    pub fn tile(&self, n: usize, range: RangeInclusive<Height>) -> Result<HeightMap> {
+        if range.is_empty() || *range.end() > 9 {
+            bail!(
+                "height range must be non-empty within 0..=9; got {:?}",
+                range
+            );
+        }
+        if let Some(h) = self
+            .0
+            .elements_row_major_iter()
+            .find(|h| !range.contains(h))
+        {
+            bail!("height {} is outside the range {:?}", h, range);
+        }
+        let (rows, columns) = (self.0.num_rows(), self.0.num_columns());
+        let len = (range.end() - range.start()) as usize + 1;
+        let elems = (0..rows * n)
+            .cartesian_product(0..columns * n)
+            .map(|(r, c)| {
+                let h = self.0[(r % rows, c % columns)] as usize + r / rows + c / columns;
+                if h > *range.end() as usize {
+                    (*range.start() as usize + (h - *range.start() as usize) % len) as Height
+                } else {
+                    h as Height
+                }
+            })
+            .collect::<Vec<_>>();
+        Ok(HeightMap(array2d::Array2D::from_row_major(
+            &elems,
+            rows * n,
+            columns * n,
+        )))
+    }
+
+    fn neighbours(&self, c: (usize, usize)) -> impl Iterator<Item = (usize, usize)> {
+        self.adjacent(c, &ORTHOGONAL)
+    }
+
+    fn adjacent(
+        &self,
+        c: (usize, usize),
+        offsets: &'static [(i64, i64)],
+    ) -> impl Iterator<Item = (usize, usize)> {
+        let (rows, columns) = (self.0.num_rows(), self.0.num_columns());
+        offsets
+            .iter()
+            .map(move |&(dr, dc)| (c.0 as i64 + dr, c.1 as i64 + dc))
+            .filter(move |&(r, c)| r >= 0 && c >= 0 && r < rows as i64 && c < columns as i64)
+            .map(|(r, c)| (r as usize, c as usize))
+    }
+
+    // Returns the cheapest cost of reaching every cell from `from`, or None if
+    // `from` is off the map.
+    pub fn distances_from(
+        &self,
+        from: (usize, usize),
+        model: &CostModel,
+    ) -> Option<array2d::Array2D<Option<Cost>>> {
+        let (rows, columns) = (self.0.num_rows(), self.0.num_columns());
+        if from.0 >= rows || from.1 >= columns {
+            return None;
+        }
+        let mut distances = array2d::Array2D::filled_with(None, rows, columns);
+        let mut queue = BinaryHeap::new();
+        queue.push(Reverse((0, from)));
+        while let Some(Reverse((cost, c))) = queue.pop() {
+            if distances[c].is_some() {
+                continue;
+            }
+            distances[c] = Some(cost);
+            for n in self.neighbours(c) {
+                if distances[n].is_none() {
+                    queue.push(Reverse((cost + model.step(self.0[c], self.0[n]), n)));
+                }
+            }
+        }
+        Some(distances)
+    }
+
+    // Finds a cheapest path with A*, guided by the Manhattan distance times
+    // the cheapest possible move.
+    pub fn shortest_path(
+        &self,
+        from: (usize, usize),
+        to: (usize, usize),
+        model: &CostModel,
+    ) -> Option<Path> {
+        let (rows, columns) = (self.0.num_rows(), self.0.num_columns());
+        if from.0 >= rows || from.1 >= columns || to.0 >= rows || to.1 >= columns {
+            return None;
+        }
+        let lowest = self.0.elements_row_major_iter().min().copied().unwrap_or(0);
+        let cheapest_move = model.step(lowest, lowest);
+        let estimate = |c: (usize, usize)| {
+            let distance = (c.0 as i64 - to.0 as i64).abs() + (c.1 as i64 - to.1 as i64).abs();
+            distance as Cost * cheapest_move
+        };
+
+        let mut costs = array2d::Array2D::filled_with(None, rows, columns);
+        let mut previous = array2d::Array2D::filled_with(None, rows, columns);
+        let mut done = array2d::Array2D::filled_with(false, rows, columns);
+        let mut queue = BinaryHeap::new();
+        costs[from] = Some(0);
+        queue.push(Reverse((estimate(from), 0, from)));
+        while let Some(Reverse((_, cost, c))) = queue.pop() {
+            if done[c] {
+                continue;
+            }
+            done[c] = true;
+            if c == to {
+                let mut cells = vec![to];
+                while let Some(p) = previous[*cells.last().unwrap()] {
+                    cells.push(p);
+                }
+                cells.reverse();
+                return Some(Path { cells, cost });
+            }
+            for n in self.neighbours(c) {
+                let next = cost + model.step(self.0[c], self.0[n]);
+                if !done[n] && !matches!(costs[n], Some(old) if old <= next) {
+                    costs[n] = Some(next);
+                    previous[n] = Some(c);
+                    queue.push(Reverse((next + estimate(n), next, n)));
+                }
+            }
+        }
+        None
+    }
+
     #[allow(clippy::if_same_then_else)]
     #[allow(clippy::needless_bool)]
     fn low_points(&self) -> Vec<(usize, usize)> {
@@ -67,45 +386,159 @@ impl HeightMap {
             .sum()
     }
 
-    fn basin_sizes(&self) -> Vec<usize> {
-        let mut basin_sizes = Vec::new();
-        let low_points = self.low_points();
-        for (r, c) in low_points {
-            let mut visit = VecDeque::new();
-            let mut seen = HashSet::new();
-            visit.push_back((r, c));
-            while !visit.is_empty() {
-                let (r, c) = visit.pop_front().unwrap();
-                seen.insert((r, c));
-                for adjacent in [(0, 1), (0, -1), (1, 0), (-1, 0)] {
-                    let next = (r as i32 + adjacent.0, c as i32 + adjacent.1);
-                    if next.0 < 0 || next.1 < 0 {
-                        continue;
-                    }
-                    let next = (next.0 as usize, next.1 as usize);
-                    if next.0 >= self.0.num_rows() || next.1 >= self.0.num_columns() {
-                        continue;
-                    }
-                    if self.0[next] == 9 || seen.contains(&next) {
-                        continue;
+    pub fn basins(&self) -> Basins {
+        self.basins_with(&BasinRules::default())
+    }
+
+    // Labels the basins, which are the connected regions of cells that aren't
+    // walls. Labels are numbered from 0 in the order each basin is first
+    // reached in a row-major scan.
+    pub fn basins_with(&self, rules: &BasinRules) -> Basins {
+        let (rows, columns) = (self.0.num_rows(), self.0.num_columns());
+        let mut sets = DisjointSet::new(rows * columns);
+        for c in self.coords() {
+            if rules.is_wall(self.0[c]) {
+                continue;
+            }
+            for n in self.adjacent(c, rules.offsets()) {
+                if n < c && !rules.is_wall(self.0[n]) {
+                    sets.union(c.0 * columns + c.1, n.0 * columns + n.1);
+                }
+            }
+        }
+
+        let mut labels = array2d::Array2D::filled_with(None, rows, columns);
+        let mut root_labels = HashMap::new();
+        for c in self.coords() {
+            if !rules.is_wall(self.0[c]) {
+                let next = root_labels.len();
+                let root = sets.find(c.0 * columns + c.1);
+                labels[c] = Some(*root_labels.entry(root).or_insert(next));
+            }
+        }
+        self.describe(labels, root_labels.len())
+    }
+
+    // Returns the lowest regions of the map: cells, or with plateaus allowed
+    // connected cells of equal height, with no lower neighbour. Walls are
+    // never low.
+    pub fn low_areas(&self, rules: &BasinRules) -> Vec<Vec<(usize, usize)>> {
+        let (rows, columns) = (self.0.num_rows(), self.0.num_columns());
+        let mut sets = DisjointSet::new(rows * columns);
+        if rules.plateaus {
+            for c in self.coords() {
+                for n in self.adjacent(c, rules.offsets()) {
+                    if n < c && self.0[n] == self.0[c] {
+                        sets.union(c.0 * columns + c.1, n.0 * columns + n.1);
                     }
-                    visit.push_back(next);
                 }
             }
-            basin_sizes.push(seen.len());
         }
-        basin_sizes
+        // An area is low until one of its cells has a neighbour outside the
+        // area that is no higher.
+        let mut low = HashMap::new();
+        let mut areas: Vec<Vec<(usize, usize)>> = Vec::new();
+        for c in self.coords() {
+            let root = sets.find(c.0 * columns + c.1);
+            let is_low = !rules.is_wall(self.0[c])
+                && self
+                    .adjacent(c, rules.offsets())
+                    .all(|n| self.0[n] > self.0[c] || sets.find(n.0 * columns + n.1) == root);
+            let area = *low.entry(root).or_insert_with(|| {
+                areas.push(Vec::new());
+                Some(areas.len() - 1)
+            });
+            match (area, is_low) {
+                (Some(i), true) => areas[i].push(c),
+                (Some(i), false) => {
+                    areas[i].clear();
+                    low.insert(root, None);
+                }
+                (None, _) => {}
+            }
+        }
+        areas.into_iter().filter(|a| !a.is_empty()).collect()
+    }
+
+    // Splits the map so that every cell that isn't a wall drains to exactly
+    // one low area, found with plateaus allowed. Basins are flooded upwards
+    // from their low areas, lowest cells first, and each cell joins the first
+    // basin to reach it. Labels follow the order of the low areas.
+    pub fn watershed(&self, rules: &BasinRules) -> Basins {
+        let rules = rules.plateaus(true);
+        let (rows, columns) = (self.0.num_rows(), self.0.num_columns());
+        let mut labels = array2d::Array2D::filled_with(None, rows, columns);
+        let mut queue = BinaryHeap::new();
+        // Ties in height go to the cell queued first.
+        let mut order = 0..;
+        let areas = self.low_areas(&rules);
+        for (label, area) in areas.iter().enumerate() {
+            for &c in area {
+                labels[c] = Some(label);
+                queue.push(Reverse((self.0[c], order.next(), c)));
+            }
+        }
+        while let Some(Reverse((_, _, c))) = queue.pop() {
+            for n in self.adjacent(c, rules.offsets()) {
+                if labels[n].is_none() && !rules.is_wall(self.0[n]) {
+                    labels[n] = labels[c];
+                    queue.push(Reverse((self.0[n], order.next(), n)));
+                }
+            }
+        }
+        self.describe(labels, areas.len())
+    }
+
+    // Builds the basins from a labelling of the map with count labels. A
+    // basin's low point is its first lowest cell in row-major order.
+    fn describe(&self, labels: array2d::Array2D<Option<usize>>, count: usize) -> Basins {
+        let mut basins: Vec<Option<Basin>> = vec![None; count];
+        for c in self.coords() {
+            let label = match labels[c] {
+                Some(label) => label,
+                None => continue,
+            };
+            let basin = basins[label].get_or_insert(Basin {
+                size: 0,
+                low_point: c,
+                top_left: c,
+                bottom_right: c,
+                perimeter: 0,
+            });
+            basin.size += 1;
+            if self.0[c] < self.0[basin.low_point] {
+                basin.low_point = c;
+            }
+            basin.top_left = (basin.top_left.0.min(c.0), basin.top_left.1.min(c.1));
+            basin.bottom_right = (basin.bottom_right.0.max(c.0), basin.bottom_right.1.max(c.1));
+            basin.perimeter += 4 - self
+                .neighbours(c)
+                .filter(|&n| labels[n] == labels[c])
+                .count();
+        }
+        Basins {
+            labels,
+            basins: basins.into_iter().flatten().collect(),
+        }
+    }
+
+    fn coords(&self) -> impl Iterator<Item = (usize, usize)> {
+        (0..self.0.num_rows()).cartesian_product(0..self.0.num_columns())
     }
 
     pub fn largest_basins_product(&self, num: usize) -> usize {
-        let mut sizes = self.basin_sizes();
+        let mut sizes = self
+            .basins()
+            .basins
+            .iter()
+            .map(|b| b.size)
+            .collect::<Vec<_>>();
         sizes.sort_by(|i, j| j.partial_cmp(i).unwrap());
         sizes.into_iter().take(num).product()
     }
 }
 
 #[cfg(test)]
-
 mod tests {
     use super::*;
 
@@ -126,4 +559,162 @@ mod tests {
         let height_map = INPUT.parse::<HeightMap>().unwrap();
         assert_eq!(1134, height_map.largest_basins_product(3));
     }
+
+    const RISK: &str = r"1163751742
+1381373672
+2136511328
+3694931569
+7463417111
+1319128137
+1359912421
+3125421639
+1293138521
+2311944581";
+
+    #[test]
+    fn test_shortest_path() {
+        let map = RISK.parse::<HeightMap>().unwrap();
+        let model = CostModel::default();
+        let path = map.shortest_path((0, 0), (9, 9), &model).unwrap();
+        assert_eq!(40, path.cost);
+        assert_eq!(
+            Some(40),
+            map.distances_from((0, 0), &model).unwrap()[(9, 9)]
+        );
+        assert_eq!(
+            "#163751742
+#381373672
+#######328
+369493##69
+7463417##1
+13191281#7
+13599124#1
+31254216#9
+12931385##
+231194458#
+",
+            path.render(&map)
+        );
+
+        let tiled = map.tile(5, 1..=9).unwrap();
+        let path = tiled.shortest_path((0, 0), (49, 49), &model).unwrap();
+        assert_eq!(315, path.cost);
+        assert_eq!(
+            Some(315),
+            tiled.distances_from((0, 0), &model).unwrap()[(49, 49)]
+        );
+        assert_eq!(None, map.shortest_path((0, 0), (10, 0), &model));
+        assert!(map.distances_from((10, 0), &model).is_none());
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 9..=1;
+        assert!(map.tile(5, reversed).is_err());
+        assert!(map.tile(5, 1..=10).is_err());
+        assert!(map.tile(5, 2..=9).is_err());
+    }
+
+    #[test]
+    fn test_climb_penalty() {
+        // Going over the 9 is shorter but climbs 8 more than going around.
+        let map = "191\n111".parse::<HeightMap>().unwrap();
+        let model = CostModel {
+            base: 1,
+            per_height: 0,
+            per_climb: 1,
+        };
+        let path = map.shortest_path((0, 0), (0, 2), &model).unwrap();
+        assert_eq!(4, path.cost);
+        assert_eq!(vec![(0, 0), (1, 0), (1, 1), (1, 2), (0, 2)], path.cells);
+        assert_eq!(
+            Some(path.cost),
+            map.distances_from((0, 0), &model).unwrap()[(0, 2)]
+        );
+    }
+
+    #[test]
+    fn test_basins() {
+        let height_map = INPUT.parse::<HeightMap>().unwrap();
+        let basins = height_map.basins();
+        assert_eq!(4, basins.basins().len());
+        assert_eq!(
+            &Basin {
+                size: 3,
+                low_point: (0, 1),
+                top_left: (0, 0),
+                bottom_right: (1, 1),
+                perimeter: 8,
+            },
+            &basins.basins()[0]
+        );
+        assert_eq!(Some(1), basins.label((0, 9)));
+        assert_eq!(None, basins.label((0, 2)));
+        let mut low_points = basins
+            .basins()
+            .iter()
+            .map(|b| b.low_point)
+            .collect::<Vec<_>>();
+        low_points.sort_unstable();
+        let mut expected = height_map.low_points();
+        expected.sort_unstable();
+        assert_eq!(expected, low_points);
+        assert_eq!(
+            vec![3, 9, 14, 9],
+            basins.basins().iter().map(|b| b.size).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_basin_rules() {
+        let map = "9119\n9229\n3949\n0990".parse::<HeightMap>().unwrap();
+        // The 1s are a plateau that strict low points miss.
+        assert_eq!(vec![(3, 0), (3, 3)], map.low_points());
+        let rules = BasinRules::default().plateaus(true);
+        assert_eq!(
+            vec![vec![(0, 1), (0, 2)], vec![(3, 0)], vec![(3, 3)]],
+            map.low_areas(&rules)
+        );
+
+        let sizes = |basins: Basins| basins.basins().iter().map(|b| b.size).collect::<Vec<_>>();
+        assert_eq!(vec![5, 2, 1], sizes(map.basins()));
+        // Diagonals join everything, through the 3 and the 4.
+        assert_eq!(vec![8], sizes(map.basins_with(&rules.diagonals(true))));
+        assert_eq!(vec![4, 1, 1], sizes(map.basins_with(&rules.walls_from(3))));
+    }
+
+    #[test]
+    fn test_watershed() {
+        // Two valleys meet along the 5s, which drain to the first basin to
+        // reach them.
+        let map = "1353\n2450".parse::<HeightMap>().unwrap();
+        let watershed = map.watershed(&BasinRules::default());
+        assert_eq!(
+            vec![(0, 0), (1, 3)],
+            watershed
+                .basins()
+                .iter()
+                .map(|b| b.low_point)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                Some(0),
+                Some(0),
+                Some(1),
+                Some(1),
+                Some(0),
+                Some(0),
+                Some(1),
+                Some(1)
+            ],
+            watershed.labels().as_row_major()
+        );
+        assert_eq!(vec![4, 4], {
+            let b = watershed.basins();
+            vec![b[0].size, b[1].size]
+        });
+        assert_eq!(8, watershed.basins()[0].perimeter);
+
+        let height_map = INPUT.parse::<HeightMap>().unwrap();
+        let basins = height_map.watershed(&BasinRules::default());
+        assert_eq!(height_map.basins(), basins);
+    }
 }
@@ -1,6 +1,7 @@
 extern crate anyhow;
 extern crate rayon;
 
+pub mod animation;
 pub mod bingo;
 pub mod cave;
 pub mod crab;
@@ -9,6 +10,7 @@ pub mod dive;
 pub mod hydrothermal;
 pub mod lanternfish;
 pub mod lava_tube;
+pub mod modular;
 pub mod octopus;
 pub mod origami;
 pub mod polymer;
@@ -0,0 +1,73 @@
+use std::ops::{Add, Mul};
+
+// Modular is an integer reduced modulo M, for counts that would otherwise
+// overflow. Any M above 0 works, since sums and products are taken in u128.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Modular<const M: u64>(u64);
+
+impl<const M: u64> Modular<M> {
+    // Referencing this fails to compile for M = 0.
+    const NONZERO_MODULUS: () = assert!(M > 0, "modulus must be above 0");
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+
+    fn reduce(v: u128) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::NONZERO_MODULUS;
+        Modular((v % M as u128) as u64)
+    }
+}
+
+impl<const M: u64> From<u64> for Modular<M> {
+    fn from(v: u64) -> Self {
+        Self::reduce(v as u128)
+    }
+}
+
+impl<const M: u64> Add for Modular<M> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::reduce(self.0 as u128 + other.0 as u128)
+    }
+}
+
+impl<const M: u64> Mul for Modular<M> {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self::reduce(self.0 as u128 * other.0 as u128)
+    }
+}
+
+impl<const M: u64> std::fmt::Display for Modular<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arithmetic() {
+        type M7 = Modular<7>;
+        assert_eq!(M7::from(3), M7::from(10));
+        assert_eq!(M7::from(1), M7::from(4) + M7::from(4));
+        assert_eq!(M7::from(6), M7::from(4) * M7::from(5));
+
+        type Big = Modular<{ (1 << 40) + 15 }>;
+        let x = Big::from((1 << 40) - 1);
+        // (-16)^2 = 256 and (-16) + (-16) = -32.
+        assert_eq!(256, (x * x).value());
+        assert_eq!((1 << 40) + 15 - 32, (x + x).value());
+        type Largest = Modular<{ u64::MAX }>;
+        assert_eq!(
+            1,
+            (Largest::from(u64::MAX - 1) * Largest::from(u64::MAX - 1)).value()
+        );
+    }
+}
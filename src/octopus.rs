@@ -1,11 +1,226 @@
+use std::collections::BTreeMap;
+use std::io;
+
 use anyhow::anyhow;
+use anyhow::bail;
 use anyhow::Result;
 use itertools::Itertools;
 
+use crate::animation::Animation;
+
 type Octopus = u8;
 pub struct OctopusGrid {
     grid: array2d::Array2D<Octopus>,
     flashes: usize,
+    rules: Rules,
+    stats: Stats,
+}
+
+// Stats records where and when octopuses have flashed. Steps are numbered
+// from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    cell_flashes: array2d::Array2D<usize>,
+    first_flashes: array2d::Array2D<Option<usize>>,
+    cascade_sizes: Vec<usize>,
+}
+
+// Bitmap is a set of cells in a grid, packed into 64-bit words.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Bitmap {
+    rows: usize,
+    columns: usize,
+    words: Vec<u64>,
+}
+
+// Recording holds the energies of a grid on successive steps, along with
+// which octopuses flashed on that step.
+pub struct Recording {
+    frames: Vec<(array2d::Array2D<Octopus>, array2d::Array2D<bool>)>,
+}
+
+// Energies are shades of blue that brighten towards a flash, which is white.
+const PALETTE: [[u8; 3]; 11] = [
+    [0, 0, 32],
+    [0, 8, 56],
+    [0, 16, 80],
+    [0, 28, 104],
+    [0, 40, 128],
+    [8, 56, 152],
+    [16, 76, 176],
+    [32, 100, 200],
+    [56, 128, 224],
+    [88, 160, 248],
+    [255, 255, 255],
+];
+const FLASH: u8 = 10;
+
+// Cycle describes the steps of a grid from some starting state: after offset
+// steps it enters a cycle of states that repeats every length steps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cycle {
+    offset: usize,
+    length: usize,
+    // The flash count after each step, starting with the count before any.
+    flash_totals: Vec<usize>,
+    // The steps before the cycle ends on which every octopus flashed.
+    synchronized: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Neighbourhood {
+    // The four orthogonally adjacent cells.
+    VonNeumann,
+    // The eight cells including diagonals.
+    Moore,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Threshold {
+    Uniform(Octopus),
+    PerCell(array2d::Array2D<Octopus>),
+}
+
+// Rules control how energy cascades through a grid. An octopus flashes when
+// its energy exceeds its threshold, which raises the energy of each of its
+// neighbours. Octopuses that flashed are reset, and the rest lose the decay
+// amount of energy at the end of the step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rules {
+    neighbourhood: Neighbourhood,
+    wrap: bool,
+    threshold: Threshold,
+    reset: Octopus,
+    decay: Octopus,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            neighbourhood: Neighbourhood::Moore,
+            wrap: false,
+            threshold: Threshold::Uniform(9),
+            reset: 0,
+            decay: 0,
+        }
+    }
+}
+
+impl Rules {
+    pub fn neighbourhood(mut self, neighbourhood: Neighbourhood) -> Self {
+        self.neighbourhood = neighbourhood;
+        self
+    }
+
+    // Makes the grid toroidal, so cells on an edge neighbour the opposite edge.
+    pub fn wrapping(mut self, wrap: bool) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn threshold(mut self, threshold: Threshold) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn reset_to(mut self, reset: Octopus) -> Self {
+        self.reset = reset;
+        self
+    }
+
+    pub fn decay(mut self, decay: Octopus) -> Self {
+        self.decay = decay;
+        self
+    }
+
+    fn offsets(&self) -> &'static [(i32, i32)] {
+        match self.neighbourhood {
+            Neighbourhood::VonNeumann => &[(-1, 0), (0, -1), (0, 1), (1, 0)],
+            Neighbourhood::Moore => &[
+                (-1, -1),
+                (-1, 0),
+                (-1, 1),
+                (0, -1),
+                (0, 1),
+                (1, -1),
+                (1, 0),
+                (1, 1),
+            ],
+        }
+    }
+
+    // Returns the neighbours of c in a grid of the given rows and columns.
+    // Wrapping around a grid less than 3 cells across can bring several
+    // offsets to the same cell, which counts once, or back to c, which
+    // doesn't count.
+    fn neighbours(
+        &self,
+        dims: (usize, usize),
+        c: (usize, usize),
+    ) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let (rows, columns) = (dims.0 as i32, dims.1 as i32);
+        let target = move |offset: &(i32, i32)| {
+            let n = (c.0 as i32 + offset.0, c.1 as i32 + offset.1);
+            let n = if self.wrap {
+                (n.0.rem_euclid(rows), n.1.rem_euclid(columns))
+            } else if n.0 >= 0 && n.1 >= 0 && n.0 < rows && n.1 < columns {
+                n
+            } else {
+                return None;
+            };
+            Some((n.0 as usize, n.1 as usize))
+        };
+        let offsets = self.offsets();
+        let small = self.wrap && (rows < 3 || columns < 3);
+        offsets.iter().enumerate().filter_map(move |(i, offset)| {
+            let n = target(offset)?;
+            if small && (n == c || offsets[..i].iter().any(|o| target(o) == Some(n))) {
+                None
+            } else {
+                Some(n)
+            }
+        })
+    }
+
+    // Steps the energies in grid, returning which octopuses flashed. Flashes
+    // spread from a worklist of octopuses that have just crossed their
+    // threshold, so each step only touches the cells a cascade reaches.
+    fn step(&self, grid: &mut array2d::Array2D<Octopus>) -> array2d::Array2D<bool> {
+        let dims = (grid.num_rows(), grid.num_columns());
+        let mut flashed = array2d::Array2D::filled_with(false, dims.0, dims.1);
+        let mut pending = Vec::new();
+        for c in (0..dims.0).cartesian_product(0..dims.1) {
+            grid[c] = grid[c].saturating_add(1);
+            if grid[c] > self.threshold_at(c) {
+                flashed[c] = true;
+                pending.push(c);
+            }
+        }
+        while let Some(c) = pending.pop() {
+            for n in self.neighbours(dims, c) {
+                grid[n] = grid[n].saturating_add(1);
+                if !flashed[n] && grid[n] > self.threshold_at(n) {
+                    flashed[n] = true;
+                    pending.push(n);
+                }
+            }
+        }
+        for c in (0..dims.0).cartesian_product(0..dims.1) {
+            if flashed[c] {
+                grid[c] = self.reset;
+            } else {
+                grid[c] = grid[c].saturating_sub(self.decay);
+            }
+        }
+        flashed
+    }
+
+    fn threshold_at(&self, c: (usize, usize)) -> Octopus {
+        match &self.threshold {
+            Threshold::Uniform(t) => *t,
+            Threshold::PerCell(grid) => grid[c],
+        }
+    }
 }
 
 impl std::str::FromStr for OctopusGrid {
@@ -20,27 +235,253 @@ impl std::str::FromStr for OctopusGrid {
         let height = s.lines().count();
         let elems = s
             .lines()
-            .map(|l| l.chars().map(|c| c as u8 - b'0').collect::<Vec<_>>())
-            .flatten()
+            .flat_map(|l| l.chars().map(|c| c as u8 - b'0').collect::<Vec<_>>())
             .collect::<Vec<_>>();
         let grid = array2d::Array2D::from_row_major(&elems, height, width);
-        Ok(OctopusGrid { grid, flashes: 0 })
+        Ok(OctopusGrid {
+            stats: Stats {
+                cell_flashes: array2d::Array2D::filled_with(0, height, width),
+                first_flashes: array2d::Array2D::filled_with(None, height, width),
+                cascade_sizes: Vec::new(),
+            },
+            grid,
+            flashes: 0,
+            rules: Rules::default(),
+        })
+    }
+}
+
+impl Stats {
+    // Returns how many times each octopus has flashed.
+    pub fn cell_flashes(&self) -> &array2d::Array2D<usize> {
+        &self.cell_flashes
+    }
+
+    // Returns the step on which each octopus first flashed, if it has.
+    pub fn first_flashes(&self) -> &array2d::Array2D<Option<usize>> {
+        &self.first_flashes
+    }
+
+    // Returns how many octopuses flashed on each step, starting with step 1.
+    pub fn cascade_sizes(&self) -> &[usize] {
+        &self.cascade_sizes
+    }
+
+    // Returns how many steps had each cascade size.
+    pub fn cascade_size_distribution(&self) -> BTreeMap<usize, usize> {
+        let mut distribution = BTreeMap::new();
+        for &size in &self.cascade_sizes {
+            *distribution.entry(size).or_insert(0) += 1;
+        }
+        distribution
+    }
+}
+
+impl From<&array2d::Array2D<bool>> for Bitmap {
+    fn from(cells: &array2d::Array2D<bool>) -> Self {
+        let mut words = vec![0; cells.num_elements() / 64 + 1];
+        cells
+            .elements_row_major_iter()
+            .enumerate()
+            .filter(|(_, &set)| set)
+            .for_each(|(i, _)| words[i / 64] |= 1 << (i % 64));
+        Bitmap {
+            rows: cells.num_rows(),
+            columns: cells.num_columns(),
+            words,
+        }
+    }
+}
+
+impl Bitmap {
+    pub fn contains(&self, c: (usize, usize)) -> bool {
+        let i = c.0 * self.columns + c.1;
+        c.0 < self.rows && c.1 < self.columns && self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&w| w == 0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..self.rows)
+            .cartesian_product(0..self.columns)
+            .filter(move |&c| self.contains(c))
+    }
+
+    pub fn intersection(&self, other: &Bitmap) -> Bitmap {
+        self.combine(other, |a, b| a & b)
+    }
+
+    pub fn union(&self, other: &Bitmap) -> Bitmap {
+        self.combine(other, |a, b| a | b)
+    }
+
+    fn combine(&self, other: &Bitmap, f: impl Fn(u64, u64) -> u64) -> Bitmap {
+        assert_eq!((self.rows, self.columns), (other.rows, other.columns));
+        Bitmap {
+            rows: self.rows,
+            columns: self.columns,
+            words: self
+                .words
+                .iter()
+                .zip(&other.words)
+                .map(|(&a, &b)| f(a, b))
+                .collect(),
+        }
     }
 }
 
+impl std::fmt::Display for Bitmap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for r in 0..self.rows {
+            for c in 0..self.columns {
+                write!(f, "{}", if self.contains((r, c)) { '#' } else { '.' })?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Recording {
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Renders each step with every octopus drawn as a square of scale pixels.
+    pub fn animation(&self, scale: usize) -> Result<Animation> {
+        let (energy, _) = &self.frames[0];
+        let (rows, columns) = (energy.num_rows(), energy.num_columns());
+        let mut animation = Animation::new(columns * scale, rows * scale, &PALETTE)?;
+        for (energy, flashed) in &self.frames {
+            let pixels = (0..rows * scale)
+                .cartesian_product(0..columns * scale)
+                .map(|(y, x)| {
+                    let c = (y / scale, x / scale);
+                    if flashed[c] {
+                        FLASH
+                    } else {
+                        energy[c].min(FLASH - 1)
+                    }
+                })
+                .collect();
+            animation.push(pixels)?;
+        }
+        Ok(animation)
+    }
+
+    // Writes each step for a terminal, redrawing over the previous step, with
+    // octopuses that flashed highlighted. The output is flushed and pause
+    // called between steps, so a caller can animate it by sleeping there.
+    pub fn write_ansi<W: io::Write>(&self, mut w: W, mut pause: impl FnMut()) -> io::Result<()> {
+        write!(w, "\x1b[2J")?;
+        for (step, (energy, flashed)) in self.frames.iter().enumerate() {
+            if step > 0 {
+                w.flush()?;
+                pause();
+            }
+            writeln!(w, "\x1b[Hstep {}", step)?;
+            for r in 0..energy.num_rows() {
+                for c in 0..energy.num_columns() {
+                    let digit = char::from_digit(energy[(r, c)].min(9) as u32, 10).unwrap();
+                    if flashed[(r, c)] {
+                        write!(w, "\x1b[1;93m{}\x1b[0m", digit)?;
+                    } else {
+                        write!(w, "\x1b[2m{}\x1b[0m", digit)?;
+                    }
+                }
+                writeln!(w)?;
+            }
+        }
+        w.flush()
+    }
+}
+
+impl Cycle {
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub fn first_synchronized_flash(&self) -> Option<usize> {
+        self.synchronized.first().copied()
+    }
+
+    // Returns whether every octopus flashes on some step in the cycle, so it
+    // keeps happening forever.
+    pub fn synchronizes_forever(&self) -> bool {
+        self.synchronized.iter().any(|&s| s > self.offset)
+    }
+
+    // Returns the flash count after the given step without simulating it.
+    pub fn flash_count_at(&self, step: u64) -> u128 {
+        let offset = self.offset as u64;
+        if step <= offset {
+            return self.flash_totals[step as usize] as u128;
+        }
+        let cycle_start = self.flash_totals[self.offset] as u128;
+        let per_cycle = (self.flash_totals[self.offset + self.length] as u128) - cycle_start;
+        let cycles = ((step - offset) / self.length as u64) as u128;
+        let rest = ((step - offset) % self.length as u64) as usize;
+        let partial = (self.flash_totals[self.offset + rest] as u128) - cycle_start;
+        cycle_start + cycles * per_cycle + partial
+    }
+}
+
+// Displays one digit per octopus, as parsed. Thresholds above 9 let energies
+// outgrow a digit, in which case they're separated and aligned instead.
 impl std::fmt::Display for OctopusGrid {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        (0..self.grid.num_rows()).for_each(|r| {
-            self.grid
-                .row_iter(r)
-                .for_each(|c| write!(f, "{}", (*c + b'0') as char).unwrap());
-            writeln!(f).unwrap();
-        });
+        let width = self
+            .grid
+            .elements_row_major_iter()
+            .map(|e| e.to_string().len())
+            .max()
+            .unwrap_or(1);
+        for r in 0..self.grid.num_rows() {
+            for (i, e) in self.grid.row_iter(r).enumerate() {
+                if width > 1 && i > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{:>width$}", e, width = width)?;
+            }
+            writeln!(f)?;
+        }
         Ok(())
     }
 }
 
 impl OctopusGrid {
+    pub fn with_rules(mut self, rules: Rules) -> Result<Self> {
+        if let Threshold::PerCell(thresholds) = &rules.threshold {
+            if thresholds.num_rows() != self.grid.num_rows()
+                || thresholds.num_columns() != self.grid.num_columns()
+            {
+                bail!(
+                    "thresholds are {}x{} but the grid is {}x{}",
+                    thresholds.num_rows(),
+                    thresholds.num_columns(),
+                    self.grid.num_rows(),
+                    self.grid.num_columns()
+                );
+            }
+        }
+        self.rules = rules;
+        Ok(self)
+    }
+
     pub fn step_n(mut self, n: usize) -> Self {
         for _ in 0..n {
             self = self.step_single();
@@ -48,63 +489,150 @@ impl OctopusGrid {
         self
     }
 
-    pub fn first_synchronized_flash(mut self) -> usize {
-        let mut step = 0;
-        let total = self.grid.num_elements();
+    // Returns the first step on which every octopus flashes, or None if the
+    // grid settles into a cycle in which that never happens. The grid steps
+    // as the hare of Brent's algorithm (see brent), so it stops once every
+    // step of the cycle has been seen.
+    pub fn first_synchronized_flash(self) -> Option<usize> {
+        let mut tortoise = self.grid.clone();
+        let mut hare = self.grid;
+        let (mut power, mut length, mut step) = (1, 0, 0);
         loop {
-            let before = self.flashes;
-            self = self.step_single();
+            let flashed = self.rules.step(&mut hare);
             step += 1;
-            if self.flashes - before == total {
-                return step;
+            if flashed.elements_row_major_iter().all(|&f| f) {
+                return Some(step);
+            }
+            length += 1;
+            if tortoise == hare {
+                return None;
+            }
+            if power == length {
+                tortoise.clone_from(&hare);
+                power *= 2;
+                length = 0;
             }
         }
     }
 
-    fn step_single(mut self) -> Self {
-        let mut blinked =
-            array2d::Array2D::filled_with(false, self.grid.num_rows(), self.grid.num_columns());
-        self.coord_iter().for_each(|(r, c)| self.grid[(r, c)] += 1);
-        let mut changed = true;
-        while changed {
-            changed = false;
-            self.coord_iter()
-                .filter(|&c| self.grid[c] > 9 && !blinked[c])
-                .collect::<Vec<_>>()
-                .into_iter()
-                .for_each(|c| {
-                    changed = true;
-                    blinked[c] = true;
-                    self.adjacent_coords(c)
-                        .into_iter()
-                        .for_each(|c| self.grid[c] += 1);
-                });
-        }
-        self.coord_iter().filter(|&c| blinked[c]).for_each(|c| {
-            self.flashes += 1;
-            self.grid[c] = 0
-        });
+    // Steps the grid until it returns to a state it has been in before, giving
+    // up unless that happens within max_steps. Since the next state depends
+    // only on the current one, the grid repeats that cycle forever after.
+    pub fn find_cycle(self, max_steps: usize) -> Result<Cycle> {
+        let (offset, length) = self
+            .brent(max_steps)
+            .ok_or_else(|| anyhow!("no cycle found within {} steps", max_steps))?;
+        let total = self.grid.num_elements();
+        let mut grid = self;
+        let mut flash_totals = vec![grid.flashes];
+        let mut synchronized = Vec::new();
+        for step in 1..=offset + length {
+            let before = grid.flashes;
+            grid.step_flashes();
+            if grid.flashes - before == total {
+                synchronized.push(step);
+            }
+            flash_totals.push(grid.flashes);
+        }
+        Ok(Cycle {
+            offset,
+            length,
+            flash_totals,
+            synchronized,
+        })
+    }
 
+    // Finds the offset and length of the grid's cycle with Brent's algorithm,
+    // which holds only two grids at a time rather than every state seen. A
+    // hare steps ahead of a tortoise that jumps to it at each power of two
+    // steps, until the hare lands on the tortoise's state.
+    fn brent(&self, max_steps: usize) -> Option<(usize, usize)> {
+        // The hare meets the tortoise within three times the steps to
+        // complete the cycle.
+        let limit = max_steps.saturating_mul(3).saturating_add(1);
+        let mut tortoise = self.grid.clone();
+        let mut hare = self.grid.clone();
+        self.rules.step(&mut hare);
+        let (mut power, mut length, mut steps) = (1, 1, 1);
+        while tortoise != hare {
+            if steps >= limit {
+                return None;
+            }
+            if power == length {
+                tortoise.clone_from(&hare);
+                power *= 2;
+                length = 0;
+            }
+            self.rules.step(&mut hare);
+            steps += 1;
+            length += 1;
+        }
+
+        // With the hare a cycle ahead, they first meet where the cycle starts.
+        let mut tortoise = self.grid.clone();
+        let mut hare = self.grid.clone();
+        for _ in 0..length {
+            self.rules.step(&mut hare);
+        }
+        let mut offset = 0;
+        while tortoise != hare {
+            self.rules.step(&mut tortoise);
+            self.rules.step(&mut hare);
+            offset += 1;
+        }
+        if offset + length > max_steps {
+            return None;
+        }
+        Some((offset, length))
+    }
+
+    fn step_single(mut self) -> Self {
+        self.step_flashes();
         self
     }
 
-    fn coord_iter(&self) -> impl Iterator<Item = (usize, usize)> {
-        (0..self.grid.num_rows()).cartesian_product(0..self.grid.num_columns())
+    // Steps the grid, returning which octopuses flashed.
+    fn step_flashes(&mut self) -> array2d::Array2D<bool> {
+        let flashed = self.rules.step(&mut self.grid);
+        let step = self.stats.cascade_sizes.len() + 1;
+        let before = self.flashes;
+        for c in self.coord_iter() {
+            if flashed[c] {
+                self.flashes += 1;
+                self.stats.cell_flashes[c] += 1;
+                self.stats.first_flashes[c].get_or_insert(step);
+            }
+        }
+        self.stats.cascade_sizes.push(self.flashes - before);
+        flashed
+    }
+
+    // Steps the grid once, returning the set of octopuses that flashed.
+    pub fn step_with_flashes(mut self) -> (Self, Bitmap) {
+        let flashed = self.step_flashes();
+        (self, Bitmap::from(&flashed))
     }
 
-    fn adjacent_coords(&self, c: (usize, usize)) -> Vec<(usize, usize)> {
-        (-1..=1)
-            .cartesian_product(-1..=1)
-            .filter(|&c| c != (0, 0))
-            .map(move |offset| (c.0 as i32 + offset.0, c.1 as i32 + offset.1))
-            .filter(|c| {
-                c.0 >= 0
-                    && c.1 >= 0
-                    && c.0 < self.grid.num_rows() as i32
-                    && c.1 < self.grid.num_columns() as i32
-            })
-            .map(|c| (c.0 as usize, c.1 as usize))
-            .collect::<Vec<_>>()
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    // Steps the grid n times, recording the initial state and every step.
+    pub fn record(mut self, n: usize) -> (Self, Recording) {
+        let dims = (self.grid.num_rows(), self.grid.num_columns());
+        let mut frames = vec![(
+            self.grid.clone(),
+            array2d::Array2D::filled_with(false, dims.0, dims.1),
+        )];
+        for _ in 0..n {
+            let flashed = self.step_flashes();
+            frames.push((self.grid.clone(), flashed));
+        }
+        (self, Recording { frames })
+    }
+
+    fn coord_iter(&self) -> impl Iterator<Item = (usize, usize)> {
+        (0..self.grid.num_rows()).cartesian_product(0..self.grid.num_columns())
     }
 
     pub fn flash_count(&self) -> usize {
@@ -113,7 +641,6 @@ impl OctopusGrid {
 }
 
 #[cfg(test)]
-
 mod tests {
     use super::*;
 
@@ -143,6 +670,220 @@ mod tests {
     #[test]
     fn test_first_synchronized_flash() {
         let grid = INPUT.parse::<OctopusGrid>().unwrap();
-        assert_eq!(195, grid.first_synchronized_flash());
+        assert_eq!(Some(195), grid.first_synchronized_flash());
+    }
+
+    #[test]
+    fn test_neighbourhood_and_wrap() {
+        // Only the centre flashes, which doesn't reach the corners.
+        let grid = "777\n797\n777".parse::<OctopusGrid>().unwrap();
+        let rules = Rules::default().neighbourhood(Neighbourhood::VonNeumann);
+        let grid = grid.with_rules(rules).unwrap().step_n(1);
+        assert_eq!("898\n909\n898\n", grid.to_string());
+
+        // With wrapping, a flash in a corner reaches the other three corners.
+        let grid = "9000\n0000\n0000\n0000".parse::<OctopusGrid>().unwrap();
+        let grid = grid.with_rules(Rules::default().wrapping(true)).unwrap();
+        assert_eq!("0212\n2212\n1111\n2212\n", grid.step_n(1).to_string());
+
+        // On a 2x2 wrapped grid every other cell is a neighbour once.
+        let grid = "90\n00".parse::<OctopusGrid>().unwrap();
+        let grid = grid.with_rules(Rules::default().wrapping(true)).unwrap();
+        assert_eq!("02\n22\n", grid.step_n(1).to_string());
+        let grid = "90\n00".parse::<OctopusGrid>().unwrap();
+        let rules = Rules::default()
+            .neighbourhood(Neighbourhood::VonNeumann)
+            .wrapping(true);
+        let grid = grid.with_rules(rules).unwrap();
+        assert_eq!("02\n21\n", grid.step_n(1).to_string());
+    }
+
+    #[test]
+    fn test_thresholds_and_decay() {
+        let thresholds = array2d::Array2D::from_row_major(&[1, 9, 9, 9], 2, 2);
+        let rules = Rules::default()
+            .neighbourhood(Neighbourhood::VonNeumann)
+            .threshold(Threshold::PerCell(thresholds))
+            .reset_to(5)
+            .decay(1);
+        let grid = "11\n11".parse::<OctopusGrid>().unwrap();
+        let grid = grid.with_rules(rules).unwrap().step_n(1);
+        assert_eq!(1, grid.flash_count());
+        assert_eq!("52\n21\n", grid.to_string());
+
+        // Energies can climb past 9 without flashing.
+        let rules = Rules::default().threshold(Threshold::Uniform(200));
+        let grid = "91\n11".parse::<OctopusGrid>().unwrap();
+        let grid = grid.with_rules(rules).unwrap().step_n(1);
+        assert_eq!("10  2\n 2  2\n", grid.to_string());
+        assert_eq!("200 192\n192 192\n", grid.step_n(190).to_string());
+
+        let bad =
+            Rules::default().threshold(Threshold::PerCell(array2d::Array2D::filled_with(9, 1, 1)));
+        let grid = "11\n11".parse::<OctopusGrid>().unwrap();
+        assert!(grid.with_rules(bad).is_err());
+    }
+
+    // Generates a grid of pseudo-random energies.
+    fn generate(rows: usize, columns: usize) -> OctopusGrid {
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut s = String::new();
+        for _ in 0..rows {
+            for _ in 0..columns {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                s.push((b'0' + (state % 10) as u8) as char);
+            }
+            s.push('\n');
+        }
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_matches_rescanning_cascade() {
+        // The cascade as it was done before the worklist: rescan the whole
+        // grid until no new octopus flashes.
+        fn rescan(
+            mut grid: array2d::Array2D<Octopus>,
+            flashes: &mut usize,
+        ) -> array2d::Array2D<Octopus> {
+            let rules = Rules::default();
+            let dims = (grid.num_rows(), grid.num_columns());
+            let coords = (0..dims.0).cartesian_product(0..dims.1).collect::<Vec<_>>();
+            let mut blinked = array2d::Array2D::filled_with(false, dims.0, dims.1);
+            coords.iter().for_each(|&c| grid[c] += 1);
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for &c in &coords {
+                    if grid[c] > 9 && !blinked[c] {
+                        changed = true;
+                        blinked[c] = true;
+                        rules.neighbours(dims, c).for_each(|n| grid[n] += 1);
+                    }
+                }
+            }
+            coords.iter().filter(|&&c| blinked[c]).for_each(|&c| {
+                *flashes += 1;
+                grid[c] = 0;
+            });
+            grid
+        }
+
+        let mut grid = generate(40, 30);
+        let mut expected = grid.grid.clone();
+        let mut flashes = 0;
+        for _ in 0..50 {
+            grid = grid.step_n(1);
+            expected = rescan(expected, &mut flashes);
+            assert_eq!(expected, grid.grid);
+        }
+        assert_eq!(flashes, grid.flash_count());
+    }
+
+    // Run with: cargo test --release -- --ignored --nocapture bench_large_grid
+    #[test]
+    #[ignore]
+    fn bench_large_grid() {
+        let grid = generate(1000, 1000);
+        let start = std::time::Instant::now();
+        let grid = grid.step_n(100);
+        println!(
+            "100 steps of a 1000x1000 grid: {} flashes in {:?}",
+            grid.flash_count(),
+            start.elapsed()
+        );
+    }
+
+    #[test]
+    fn test_find_cycle() {
+        let grid = INPUT.parse::<OctopusGrid>().unwrap();
+        let cycle = grid.find_cycle(1000).unwrap();
+        // Once synchronized, every octopus flashes every 10 steps.
+        assert_eq!(195, cycle.offset());
+        assert_eq!(10, cycle.length());
+        assert_eq!(Some(195), cycle.first_synchronized_flash());
+        assert!(cycle.synchronizes_forever());
+        assert_eq!(1656, cycle.flash_count_at(100));
+        let steps = 1_000_000_000_000u64;
+        assert_eq!(
+            cycle.flash_count_at(195) + (steps - 195) as u128 / 10 * 100,
+            cycle.flash_count_at(steps)
+        );
+
+        // A lone octopus that loses all its energy each step never flashes.
+        let grid = "5".parse::<OctopusGrid>().unwrap();
+        let grid = grid.with_rules(Rules::default().decay(9)).unwrap();
+        let cycle = grid.find_cycle(10).unwrap();
+        assert_eq!((1, 1), (cycle.offset(), cycle.length()));
+        assert!(!cycle.synchronizes_forever());
+        assert_eq!(0, cycle.flash_count_at(1_000_000));
+
+        // The cycle completes on step 205.
+        let grid = INPUT.parse::<OctopusGrid>().unwrap();
+        assert!(grid.find_cycle(204).is_err());
+        let grid = INPUT.parse::<OctopusGrid>().unwrap();
+        let cycle = grid.find_cycle(205).unwrap();
+        assert_eq!((195, 10), (cycle.offset(), cycle.length()));
+        let grid = "5".parse::<OctopusGrid>().unwrap();
+        let grid = grid.with_rules(Rules::default().decay(9)).unwrap();
+        assert_eq!(None, grid.first_synchronized_flash());
+    }
+
+    #[test]
+    fn test_record() {
+        let grid = "19\n11".parse::<OctopusGrid>().unwrap();
+        let (grid, recording) = grid.record(2);
+        assert_eq!(3, recording.len());
+        assert_eq!(1, grid.flash_count());
+
+        let mut ansi = Vec::new();
+        let mut pauses = 0;
+        recording.write_ansi(&mut ansi, || pauses += 1).unwrap();
+        assert_eq!(2, pauses);
+        let ansi = String::from_utf8(ansi).unwrap();
+        assert!(ansi.contains("step 1\n\x1b[2m3\x1b[0m\x1b[1;93m0\x1b[0m\n"));
+
+        let animation = recording.animation(2).unwrap();
+        let mut ppm = Vec::new();
+        animation.write_ppm(1, &mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n4 4\n255\n"));
+        // The top right octopus flashed, so it is drawn white.
+        assert_eq!([255, 255, 255], ppm[ppm.len() - 48 + 6..][..3]);
+    }
+
+    #[test]
+    fn test_stats() {
+        let grid = INPUT.parse::<OctopusGrid>().unwrap().step_n(195);
+        let stats = grid.stats();
+        assert_eq!(
+            grid.flash_count(),
+            stats
+                .cell_flashes()
+                .elements_row_major_iter()
+                .sum::<usize>()
+        );
+        assert_eq!(Some(&100), stats.cascade_sizes().last());
+        assert_eq!(Some(&1), stats.cascade_size_distribution().get(&100));
+        assert_eq!(35, stats.cascade_sizes()[0..2].iter().sum::<usize>());
+        // The top left octopus starts at 5 and first flashes on step 3.
+        assert_eq!(Some(3), stats.first_flashes()[(0, 0)]);
+        assert!(stats
+            .first_flashes()
+            .elements_row_major_iter()
+            .all(|f| f.is_some()));
+    }
+
+    #[test]
+    fn test_step_with_flashes() {
+        let grid = "19\n11".parse::<OctopusGrid>().unwrap();
+        let (grid, first) = grid.step_with_flashes();
+        assert_eq!(".#\n..\n", first.to_string());
+        assert_eq!(vec![(0, 1)], first.iter().collect::<Vec<_>>());
+        let (_, second) = grid.step_with_flashes();
+        assert!(second.is_empty());
+        assert_eq!(1, first.union(&second).len());
+        assert!(first.intersection(&second).is_empty());
     }
 }
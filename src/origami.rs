@@ -73,7 +73,7 @@ impl Page {
     }
 
     pub fn follow_first(&mut self, instructions: &Instructions) {
-        self.step(instructions.0.get(0).unwrap());
+        self.step(instructions.0.first().unwrap());
     }
 
     pub fn follow_all(&mut self, instructions: &Instructions) {
@@ -111,7 +111,6 @@ impl std::fmt::Display for Page {
 }
 
 #[cfg(test)]
-
 mod tests {
     use super::*;
 
@@ -119,7 +119,6 @@ impl std::str::FromStr for Rules {
 }
 
 #[cfg(test)]
-
 mod tests {
     use super::*;
 
@@ -1,18 +1,23 @@
 use anyhow::anyhow;
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
+use itertools::Itertools;
 use rayon::iter::IntoParallelRefIterator;
 use rayon::iter::ParallelIterator;
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::io;
 
 use crate::util;
 
-type Digit = HashSet<char>;
+// Digit is the set of wires lit for one digit, as a bitmask with bit i set if
+// the i-th letter is lit.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Digit(u32);
 
 pub struct Entry {
-    signal_patterns: [Digit; 10],
-    output: [Digit; 4],
+    signal_patterns: Vec<Digit>,
+    output: Vec<Digit>,
 }
 
 pub struct Entries(Vec<Entry>);
@@ -27,19 +32,16 @@ impl std::str::FromStr for Entry {
         let signal_patterns = first
             .trim_matches(char::is_whitespace)
             .split_whitespace()
-            .map(|s| s.chars().collect::<HashSet<_>>())
-            .collect::<Vec<_>>();
+            .map(str::parse::<Digit>)
+            .collect::<Result<Vec<_>>>()?;
         let output = second
             .trim_matches(char::is_whitespace)
             .split_whitespace()
-            .map(|s| s.chars().collect::<HashSet<_>>())
-            .collect::<Vec<_>>();
-        let signal_patterns = signal_patterns.try_into().map_err(|v: Vec<Digit>| {
-            anyhow!("signal_patterns got {} elements; want 10", v.len())
-        })?;
-        let output = output
-            .try_into()
-            .map_err(|v: Vec<Digit>| anyhow!("output got {} elements; want 4", v.len()))?;
+            .map(str::parse::<Digit>)
+            .collect::<Result<Vec<_>>>()?;
+        if signal_patterns.is_empty() || output.is_empty() {
+            bail!("expected signal patterns and output in: {}", s);
+        }
         Ok(Self {
             signal_patterns,
             output,
@@ -47,6 +49,460 @@ impl std::str::FromStr for Entry {
     }
 }
 
+impl std::str::FromStr for Digit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.bytes().try_fold(Digit(0), |acc, b| {
+            if !b.is_ascii_lowercase() {
+                bail!("unexpected wire {:?} in: {}", b as char, s);
+            }
+            let bit = 1 << (b - b'a');
+            if acc.0 & bit != 0 {
+                bail!("repeated wire {:?} in: {}", b as char, s);
+            }
+            Ok(Digit(acc.0 | bit))
+        })
+    }
+}
+
+// Displays the lit wires in alphabetical order.
+impl std::fmt::Display for Digit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut bits = self.0;
+        while bits != 0 {
+            write!(f, "{}", (b'a' + bits.trailing_zeros() as u8) as char)?;
+            bits &= bits - 1;
+        }
+        Ok(())
+    }
+}
+
+impl Digit {
+    pub fn len(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(&self, wire: char) -> bool {
+        wire.is_ascii_lowercase() && self.0 & (1 << (wire as u8 - b'a')) != 0
+    }
+
+    // Returns the number of wires lit in both digits.
+    pub fn common(&self, other: &Digit) -> usize {
+        (self.0 & other.0).count_ones() as usize
+    }
+}
+
+// Glyphs is a display's character set: the segments each character lights
+// when the display is wired correctly. Segments are named by letters from 'a'.
+pub struct Glyphs {
+    segments: usize,
+    glyphs: Vec<(char, u32)>,
+    // The signature of each segment; see match_signatures.
+    signatures: [u64; 26],
+}
+
+// Seven-segment layout:
+//
+//  aaaa
+// b    c
+// b    c
+//  dddd
+// e    f
+// e    f
+//  gggg
+const DECIMAL: [(char, &str); 10] = [
+    ('0', "abcefg"),
+    ('1', "cf"),
+    ('2', "acdeg"),
+    ('3', "acdfg"),
+    ('4', "bcdf"),
+    ('5', "abdfg"),
+    ('6', "abdefg"),
+    ('7', "acf"),
+    ('8', "abcdefg"),
+    ('9', "abcdfg"),
+];
+
+const HEX_LETTERS: [(char, &str); 6] = [
+    ('A', "abcdef"),
+    ('b', "bdefg"),
+    ('C', "abeg"),
+    ('d', "cdefg"),
+    ('E', "abdeg"),
+    ('F', "abde"),
+];
+
+// Fourteen-segment layout, with diagonals i, k, l and n and the center
+// verticals j and m:
+//
+//  aaaaaaa
+// f i  j  k b
+// f  i j k  b
+//  ggg   hhh
+// e  l m n  c
+// e l  m  n c
+//  ddddddd
+const FOURTEEN_SEGMENT: [(char, &str); 36] = [
+    ('0', "abcdefkl"),
+    ('1', "bc"),
+    ('2', "abdegh"),
+    ('3', "abcdh"),
+    ('4', "bcfgh"),
+    ('5', "acdfgh"),
+    ('6', "acdefgh"),
+    ('7', "abc"),
+    ('8', "abcdefgh"),
+    ('9', "abcdfgh"),
+    ('A', "abcefgh"),
+    ('B', "abcdhjm"),
+    ('C', "adef"),
+    ('D', "abcdjm"),
+    ('E', "adefg"),
+    ('F', "aefg"),
+    ('G', "acdefh"),
+    ('H', "bcefgh"),
+    ('I', "adjm"),
+    ('J', "bcde"),
+    ('K', "efgkn"),
+    ('L', "def"),
+    ('M', "bcefik"),
+    ('N', "bcefin"),
+    ('O', "abcdef"),
+    ('P', "abefgh"),
+    ('Q', "abcdefn"),
+    ('R', "abefghn"),
+    ('S', "acdhi"),
+    ('T', "ajm"),
+    ('U', "bcdef"),
+    ('V', "efkl"),
+    ('W', "bcefln"),
+    ('X', "ikln"),
+    ('Y', "ikm"),
+    ('Z', "adkl"),
+];
+
+// Sixteen-segment layout, which splits the top and bottom bars in two:
+//
+//  aaa bbb
+// h i j k c
+// h  ijk  c
+//  ppp lll
+// g  onm  d
+// g o n m d
+//  fff eee
+const SIXTEEN_SEGMENT: [(char, &str); 36] = [
+    ('0', "abcdefghko"),
+    ('1', "bcd"),
+    ('2', "abcefglp"),
+    ('3', "abcdefl"),
+    ('4', "cdhlp"),
+    ('5', "abdefhlp"),
+    ('6', "abdefghlp"),
+    ('7', "abcd"),
+    ('8', "abcdefghlp"),
+    ('9', "abcdefhlp"),
+    ('A', "abcdghlp"),
+    ('B', "abcdefjln"),
+    ('C', "abefgh"),
+    ('D', "abcdefjn"),
+    ('E', "abefghp"),
+    ('F', "abghp"),
+    ('G', "abdefghl"),
+    ('H', "cdghlp"),
+    ('I', "abefjn"),
+    ('J', "cdeo"),
+    ('K', "ghkmp"),
+    ('L', "efgh"),
+    ('M', "cdghik"),
+    ('N', "cdghim"),
+    ('O', "abcdefgh"),
+    ('P', "abcghlp"),
+    ('Q', "abcdefghm"),
+    ('R', "abcghlmp"),
+    ('S', "abdefil"),
+    ('T', "abjn"),
+    ('U', "cdefgh"),
+    ('V', "ghko"),
+    ('W', "cdghmo"),
+    ('X', "ikmo"),
+    ('Y', "ikn"),
+    ('Z', "abefko"),
+];
+
+impl Glyphs {
+    pub fn new(segments: usize, table: &[(char, &str)]) -> Result<Glyphs> {
+        if segments == 0 || segments > 26 {
+            bail!("segment count must be in 1..=26; got {}", segments);
+        }
+        let glyphs = table
+            .iter()
+            .map(|&(c, lit)| {
+                lit.parse::<Digit>()
+                    .and_then(|lit| checked_mask(&lit, segments))
+                    .map(|m| (c, m))
+                    .context(format!("bad segments for glyph {:?}", c))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if glyphs.len() > 128 {
+            bail!("at most 128 glyphs are supported; got {}", glyphs.len());
+        }
+        for (i, &(c, m)) in glyphs.iter().enumerate() {
+            if let Some(&(other, _)) = glyphs[..i].iter().find(|&&(_, om)| om == m) {
+                bail!("glyphs {:?} and {:?} light the same segments", other, c);
+            }
+        }
+        let mut signatures = [0; 26];
+        for &(_, g) in &glyphs {
+            add_signature(&mut signatures, g);
+        }
+        Ok(Glyphs {
+            segments,
+            glyphs,
+            signatures,
+        })
+    }
+
+    pub fn decimal() -> Glyphs {
+        Glyphs::new(7, &DECIMAL).unwrap()
+    }
+
+    // Hexadecimal digits on seven segments, with lowercase b and d so they
+    // differ from 8 and 0.
+    pub fn hex() -> Glyphs {
+        let table = DECIMAL
+            .iter()
+            .chain(HEX_LETTERS.iter())
+            .copied()
+            .collect::<Vec<_>>();
+        Glyphs::new(7, &table).unwrap()
+    }
+
+    pub fn fourteen_segment() -> Glyphs {
+        Glyphs::new(14, &FOURTEEN_SEGMENT).unwrap()
+    }
+
+    pub fn sixteen_segment() -> Glyphs {
+        Glyphs::new(16, &SIXTEEN_SEGMENT).unwrap()
+    }
+
+    pub fn segments(&self) -> usize {
+        self.segments
+    }
+
+    // Finds the unique wiring under which every pattern lights one of the
+    // glyphs. There must be at least as many patterns as glyphs, so a line
+    // that lost some is rejected rather than solved from what remains.
+    //
+    // Each wire starts out able to drive any segment. Every pattern narrows
+    // this down: a wire in the pattern must drive a segment lit by some glyph
+    // of the same size, and a wire outside it a segment that glyph leaves dark.
+    // Once no more can be deduced, any remaining choices are searched
+    // exhaustively so that ambiguous wirings are reported rather than guessed.
+    pub fn solve(&self, patterns: &[Digit]) -> Result<Wiring> {
+        if patterns.len() < self.glyphs.len() {
+            bail!(
+                "expected at least {} signal patterns; got {}",
+                self.glyphs.len(),
+                patterns.len()
+            );
+        }
+        for d in patterns {
+            checked_mask(d, self.segments)?;
+        }
+        let all = (1u32 << self.segments) - 1;
+        let mut candidates = [all; 26];
+        let candidates = &mut candidates[..self.segments];
+        if patterns.len() == self.glyphs.len() {
+            self.match_signatures(patterns, candidates);
+            if let Some(wiring) = self.forced_wiring(patterns, candidates) {
+                return Ok(wiring);
+            }
+            // The patterns may not be the glyphs in some order, in which case
+            // the signatures prove nothing.
+            candidates.iter_mut().for_each(|c| *c = all);
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &Digit(p) in patterns {
+                // The segments lit and left dark by any glyph this pattern
+                // could still be.
+                let (lit, dark) = self
+                    .glyphs
+                    .iter()
+                    .map(|&(_, g)| g)
+                    .filter(|&g| g.count_ones() == p.count_ones())
+                    .filter(|&g| {
+                        candidates.iter().enumerate().all(|(w, &c)| {
+                            let lit = if p & (1 << w) != 0 { g } else { all & !g };
+                            c & lit != 0
+                        })
+                    })
+                    .fold((0, 0), |(lit, dark), g| (lit | g, dark | (all & !g)));
+                if lit | dark == 0 {
+                    bail!(
+                        "contradictory wiring: pattern {} matches no glyph",
+                        Digit(p)
+                    );
+                }
+                for (w, c) in candidates.iter_mut().enumerate() {
+                    let allowed = if p & (1 << w) != 0 { lit } else { dark };
+                    if *c & allowed != *c {
+                        *c &= allowed;
+                        changed = true;
+                    }
+                }
+            }
+            for w in 0..self.segments {
+                let c = candidates[w];
+                if c == 0 {
+                    bail!(
+                        "contradictory wiring: wire {} can drive no segment",
+                        (b'a' + w as u8) as char
+                    );
+                }
+                if c.count_ones() == 1 {
+                    for (other, oc) in candidates.iter_mut().enumerate() {
+                        if other != w && *oc & c != 0 {
+                            *oc &= !c;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Glyph sets are bitsets of indices into self.glyphs. Each pattern
+        // starts out as any glyph of its size.
+        let mut lit_by = [0u128; 26];
+        for (i, &(_, g)) in self.glyphs.iter().enumerate() {
+            for (s, lit) in lit_by.iter_mut().enumerate() {
+                if g & (1 << s) != 0 {
+                    *lit |= 1 << i;
+                }
+            }
+        }
+        let mut possible = vec![0u128; (self.segments + 1) * patterns.len()];
+        for (set, &Digit(p)) in possible.iter_mut().zip(patterns) {
+            for (i, &(_, g)) in self.glyphs.iter().enumerate() {
+                if g.count_ones() == p.count_ones() {
+                    *set |= 1 << i;
+                }
+            }
+        }
+        let mut solutions = Vec::new();
+        let mut search = Search {
+            patterns,
+            candidates,
+            lit_by,
+            all_glyphs: u128::MAX
+                .checked_shr(128 - self.glyphs.len() as u32)
+                .unwrap_or(0),
+            assigned: [0; 26],
+            possible,
+            solutions: &mut solutions,
+        };
+        search.run(0, 0);
+        match solutions.len() {
+            0 => bail!("contradictory wiring: no permutation lights only glyphs"),
+            1 => Ok(solutions.pop().unwrap()),
+            _ => bail!(
+                "ambiguous wiring: both {} and {} are consistent",
+                solutions[0],
+                solutions[1]
+            ),
+        }
+    }
+
+    // If the patterns are exactly the glyphs in some order, a wire and the
+    // segment it drives must appear in the same number of patterns and glyphs
+    // of each size. Each wire's appearances are summed into a signature, with
+    // a pseudo-random weight per size, which rules out most segments at once.
+    fn match_signatures(&self, patterns: &[Digit], candidates: &mut [u32]) {
+        let mut wires = [0u64; 26];
+        for &Digit(p) in patterns {
+            add_signature(&mut wires, p);
+        }
+        for (w, c) in candidates.iter_mut().enumerate() {
+            for (s, &signature) in self.signatures[..self.segments].iter().enumerate() {
+                if signature != wires[w] {
+                    *c &= !(1 << s);
+                }
+            }
+        }
+    }
+
+    // Returns the wiring if the signatures leave each wire a single segment
+    // and it lights every glyph exactly once. That shows the patterns are the
+    // glyphs in some order, so the signatures hold and no other wiring can.
+    fn forced_wiring(&self, patterns: &[Digit], candidates: &[u32]) -> Option<Wiring> {
+        let mut used = 0;
+        for &c in candidates {
+            if c.count_ones() != 1 || used & c != 0 {
+                return None;
+            }
+            used |= c;
+        }
+        let wiring = Wiring(
+            candidates
+                .iter()
+                .map(|c| c.trailing_zeros() as u8)
+                .collect(),
+        );
+        let mut lit = 0u128;
+        for &Digit(p) in patterns {
+            let segments = wiring.map(p);
+            let i = self.glyphs.iter().position(|&(_, g)| g == segments)?;
+            lit |= 1 << i;
+        }
+        if lit.count_ones() as usize == self.glyphs.len() {
+            Some(wiring)
+        } else {
+            None
+        }
+    }
+
+    pub fn decode(&self, wiring: &Wiring, digit: &Digit) -> Result<char> {
+        let segments = wiring.map(checked_mask(digit, self.segments)?);
+        self.glyphs
+            .iter()
+            .find(|&&(_, m)| m == segments)
+            .map(|&(c, _)| c)
+            .ok_or_else(|| anyhow!("{} is not a glyph under wiring {}", digit, wiring))
+    }
+}
+
+// Wiring maps each scrambled wire to the segment it drives: wire i (the i-th
+// letter) lights segment self.0[i].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wiring(Vec<u8>);
+
+impl Wiring {
+    pub fn segment_for(&self, wire: char) -> Option<char> {
+        let i = (wire as u8).checked_sub(b'a')? as usize;
+        self.0.get(i).map(|&s| (b'a' + s) as char)
+    }
+
+    fn map(&self, wires: u32) -> u32 {
+        map_wires(&self.0, wires)
+    }
+}
+
+// Displays the segment driven by each wire, in wire order.
+impl std::fmt::Display for Wiring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for &s in &self.0 {
+            write!(f, "{}", (b'a' + s) as char)?;
+        }
+        Ok(())
+    }
+}
+
 impl Entry {
     fn output_unique_digit_count(&self) -> usize {
         self.output
@@ -58,67 +514,437 @@ impl Entry {
             .sum()
     }
 
-    fn digit_with_num_segments(&self, num: usize) -> Option<&HashSet<char>> {
-        self.all_digits().find(|hs| hs.len() == num)
+    // Deduces the wiring from the signal patterns, failing if no wiring or
+    // more than one wiring is consistent with them.
+    pub fn wiring(&self) -> Result<Wiring> {
+        self.wiring_for(&Glyphs::decimal())
     }
 
-    fn all_digits(&self) -> impl Iterator<Item = &std::collections::HashSet<char>> + '_ {
-        self.signal_patterns.iter().chain(self.output.iter())
+    pub fn wiring_for(&self, glyphs: &Glyphs) -> Result<Wiring> {
+        glyphs
+            .solve(&self.signal_patterns)
+            .with_context(|| format!("failed to solve: {}", self))
     }
 
-    pub fn output_value(&self) -> Result<i32> {
-        let one = self
-            .digit_with_num_segments(2)
-            .ok_or_else(|| anyhow!("no 1 in: {}", self.to_string()))?;
-        let four = self
-            .digit_with_num_segments(4)
-            .ok_or_else(|| anyhow!("no 4 in: {}", self.to_string()))?;
-        let seven = self
-            .digit_with_num_segments(3)
-            .ok_or_else(|| anyhow!("no 3 in: {}", self.to_string()))?;
-
-        let output = self
-            .output
+    // Decodes the output as characters of the given glyph set.
+    pub fn decode(&self, glyphs: &Glyphs) -> Result<String> {
+        let wiring = self.wiring_for(glyphs)?;
+        self.output
             .iter()
-            .map(|hs| {
-                let intersect_one = hs.intersection(one).count();
-                let intersect_four = hs.intersection(four).count();
-                let intersect_seven = hs.intersection(seven).count();
-                match (hs.len(), intersect_one, intersect_four, intersect_seven) {
-                    (2, _, _, _) => 1,
-                    (5, 1, 2, 2) => 2,
-                    (5, 2, 3, 3) => 3,
-                    (4, _, _, _) => 4,
-                    (5, 1, 3, 2) => 5,
-                    (6, 1, 3, 2) => 6,
-                    (3, _, _, _) => 7,
-                    (7, _, _, _) => 8,
-                    (6, 2, 4, 3) => 9,
-                    (6, 2, 3, 3) => 0,
-                    _ => panic!(
-                        "failed to match profile: {:?}",
-                        (hs.len(), intersect_one, intersect_four, intersect_seven)
-                    ),
-                }
-            })
-            .fold(0, |acc, digit| acc * 10 + digit);
-        Ok(output)
+            .map(|d| glyphs.decode(&wiring, d))
+            .collect()
+    }
+
+    pub fn output_value(&self) -> Result<i32> {
+        self.output_value_for(&Glyphs::decimal())
+    }
+
+    fn output_value_for(&self, glyphs: &Glyphs) -> Result<i32> {
+        let wiring = self.wiring_for(glyphs)?;
+        self.output.iter().try_fold(0i32, |acc, d| {
+            let c = glyphs.decode(&wiring, d)?;
+            c.to_digit(10)
+                .and_then(|v| acc.checked_mul(10)?.checked_add(v as i32))
+                .ok_or_else(|| anyhow!("failed to parse output: {:?} after {}", c, acc))
+        })
+    }
+}
+
+// Adds a pattern or glyph to the signatures of the wires or segments it
+// lights, weighted pseudo-randomly by its size.
+fn add_signature(signatures: &mut [u64; 26], mut mask: u32) {
+    let size = mask.count_ones();
+    let weight = (size as u64 + 1)
+        .wrapping_mul(0x9e3779b97f4a7c15)
+        .rotate_left(size);
+    while mask != 0 {
+        let i = mask.trailing_zeros() as usize;
+        mask &= mask - 1;
+        signatures[i] = signatures[i].wrapping_add(weight);
+    }
+}
+
+// Returns the segments lit by the given wires, where wire i drives segment
+// assigned[i]. Wires beyond the assignment are ignored.
+fn map_wires(assigned: &[u8], mut wires: u32) -> u32 {
+    let mut segments = 0;
+    while wires != 0 {
+        let w = wires.trailing_zeros() as usize;
+        wires &= wires - 1;
+        match assigned.get(w) {
+            Some(&s) => segments |= 1 << s,
+            None => break,
+        }
+    }
+    segments
+}
+
+fn checked_mask(digit: &Digit, segments: usize) -> Result<u32> {
+    if digit.0 >> segments != 0 {
+        bail!("{} uses wires beyond the {} segments", digit, segments);
+    }
+    Ok(digit.0)
+}
+
+// Search tries every assignment of the remaining wires allowed by the
+// candidates, stopping after two solutions since that already proves
+// ambiguity. The first n wires of assigned hold the partial wiring, and the
+// n-th row of possible holds the glyphs each pattern could still be under it.
+// Once every wire is assigned, each pattern can only be the glyph it lights.
+struct Search<'a> {
+    patterns: &'a [Digit],
+    candidates: &'a [u32],
+    // The glyphs lighting each segment.
+    lit_by: [u128; 26],
+    all_glyphs: u128,
+    assigned: [u8; 26],
+    possible: Vec<u128>,
+    solutions: &'a mut Vec<Wiring>,
+}
+
+impl Search<'_> {
+    fn run(&mut self, wire: usize, used: u32) {
+        if self.solutions.len() > 1 {
+            return;
+        }
+        if wire == self.candidates.len() {
+            self.solutions.push(Wiring(self.assigned[..wire].to_vec()));
+            return;
+        }
+        let mut options = self.candidates[wire] & !used;
+        while options != 0 {
+            let segment = options.trailing_zeros();
+            options &= options - 1;
+            self.assigned[wire] = segment as u8;
+            if self.narrow(wire, segment as usize) {
+                self.run(wire + 1, used | 1 << segment);
+            }
+        }
+    }
+
+    // Fills in the next row of possible glyphs given that wire drives
+    // segment, returning whether every pattern could still be a glyph.
+    fn narrow(&mut self, wire: usize, segment: usize) -> bool {
+        let n = self.patterns.len();
+        let (done, rest) = self.possible.split_at_mut((wire + 1) * n);
+        let current = &done[wire * n..];
+        let lit = self.lit_by[segment];
+        let dark = self.all_glyphs & !lit;
+        for (i, &Digit(p)) in self.patterns.iter().enumerate() {
+            let allowed = if p & (1 << wire) != 0 { lit } else { dark };
+            rest[i] = current[i] & allowed;
+            if rest[i] == 0 {
+                return false;
+            }
+        }
+        true
     }
 }
 
 impl std::fmt::Display for Entry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for hs in &self.signal_patterns {
-            write!(f, "{} ", hs.iter().collect::<String>())?;
+        for d in &self.signal_patterns {
+            write!(f, "{} ", d)?;
         }
         write!(f, "| ")?;
-        for hs in &self.output {
-            write!(f, "{} ", hs.iter().collect::<String>())?;
+        for d in &self.output {
+            write!(f, "{} ", d)?;
         }
         Ok(())
     }
 }
 
+// Diagnosis explains an entry from a panel with faulty segments. A dead
+// segment never lights and a stuck segment is always lit. Each output
+// character comes with the fraction of equally likely explanations that agree
+// with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnosis {
+    pub wiring: Wiring,
+    pub dead: Vec<char>,
+    pub stuck: Vec<char>,
+    pub output: Vec<(char, f64)>,
+}
+
+// Hypothesis is one explanation of an entry: a wiring and the faulty segments,
+// along with the glyphs each output could be.
+struct Hypothesis {
+    wiring: Wiring,
+    dead: u32,
+    stuck: u32,
+    output: Vec<Vec<char>>,
+}
+
+impl Glyphs {
+    // Finds the wirings that explain the entry with the fewest dead and stuck
+    // segments. Every signal pattern must be a different glyph, and every
+    // output some glyph, once the faulty segments are ignored. This tries
+    // every permutation of the wires, so it is limited to small displays.
+    pub fn diagnose(&self, patterns: &[Digit], output: &[Digit]) -> Result<Diagnosis> {
+        if self.segments > 8 {
+            bail!(
+                "can only diagnose displays of up to 8 segments; got {}",
+                self.segments
+            );
+        }
+        let all = (1u32 << self.segments) - 1;
+        let patterns = patterns
+            .iter()
+            .map(|d| checked_mask(d, self.segments))
+            .collect::<Result<Vec<_>>>()?;
+        let output = output
+            .iter()
+            .map(|d| checked_mask(d, self.segments))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut best: Vec<Hypothesis> = Vec::new();
+        let mut best_faults = u32::MAX;
+        for perm in (0..self.segments as u8).permutations(self.segments) {
+            let wiring = Wiring(perm);
+            let mapped = patterns.iter().map(|&p| wiring.map(p)).collect::<Vec<_>>();
+            let mapped_output = output.iter().map(|&p| wiring.map(p)).collect::<Vec<_>>();
+            let lit = mapped.iter().chain(&mapped_output);
+            let always = lit.clone().fold(all, |acc, &m| acc & m);
+            let never = all & !lit.fold(0, |acc, &m| acc | m);
+            for stuck in submasks(always) {
+                for dead in submasks(never) {
+                    let faults = (stuck | dead).count_ones();
+                    if faults > best_faults {
+                        continue;
+                    }
+                    let working = all & !(stuck | dead);
+                    let matches = |m: u32| {
+                        self.glyphs
+                            .iter()
+                            .filter(|&&(_, g)| g & working == m & working)
+                            .map(|&(c, _)| c)
+                            .collect::<Vec<_>>()
+                    };
+                    let candidates = mapped.iter().map(|&m| matches(m)).collect::<Vec<_>>();
+                    let output = mapped_output
+                        .iter()
+                        .map(|&m| matches(m))
+                        .collect::<Vec<_>>();
+                    if output.iter().any(Vec::is_empty) || !distinct_glyphs(&candidates) {
+                        continue;
+                    }
+                    if faults < best_faults {
+                        best_faults = faults;
+                        best.clear();
+                    }
+                    best.push(Hypothesis {
+                        wiring: wiring.clone(),
+                        dead,
+                        stuck,
+                        output,
+                    });
+                }
+            }
+        }
+        if best.is_empty() {
+            bail!("no wiring explains the entry, even with faulty segments");
+        }
+
+        // Each explanation is equally likely, and splits its vote evenly
+        // between the glyphs an output could be.
+        let votes = |i: usize, c: char| {
+            best.iter()
+                .filter(|h| h.output[i].contains(&c))
+                .map(|h| 1.0 / h.output[i].len() as f64)
+                .sum::<f64>()
+                / best.len() as f64
+        };
+        let output = (0..output.len())
+            .map(|i| {
+                self.glyphs
+                    .iter()
+                    .map(|&(c, _)| (c, votes(i, c)))
+                    .fold(('?', 0.0), |acc, v| if v.1 > acc.1 { v } else { acc })
+            })
+            .collect::<Vec<_>>();
+        let likeliest = best
+            .iter()
+            .max_by(|a, b| {
+                let certainty = |h: &Hypothesis| {
+                    h.output
+                        .iter()
+                        .map(|o| 1.0 / o.len() as f64)
+                        .product::<f64>()
+                };
+                certainty(a).partial_cmp(&certainty(b)).unwrap()
+            })
+            .unwrap();
+        Ok(Diagnosis {
+            wiring: likeliest.wiring.clone(),
+            dead: Digit(likeliest.dead).to_string().chars().collect(),
+            stuck: Digit(likeliest.stuck).to_string().chars().collect(),
+            output,
+        })
+    }
+}
+
+impl Entry {
+    pub fn diagnose(&self, glyphs: &Glyphs) -> Result<Diagnosis> {
+        glyphs
+            .diagnose(&self.signal_patterns, &self.output)
+            .context(format!("failed to diagnose: {}", self))
+    }
+}
+
+// Returns every subset of the bits in mask, smallest first.
+fn submasks(mask: u32) -> Vec<u32> {
+    let mut subsets = vec![0];
+    let mut sub = mask;
+    while sub != 0 {
+        subsets.push(sub);
+        sub = (sub - 1) & mask;
+    }
+    subsets.sort_by_key(|m| m.count_ones());
+    subsets
+}
+
+// Whether each pattern can be a different glyph, given the glyphs each could
+// be. This is a bipartite matching, found with augmenting paths.
+fn distinct_glyphs(candidates: &[Vec<char>]) -> bool {
+    fn augment(
+        p: usize,
+        candidates: &[Vec<char>],
+        owner: &mut HashMap<char, usize>,
+        seen: &mut Vec<char>,
+    ) -> bool {
+        for &c in &candidates[p] {
+            if seen.contains(&c) {
+                continue;
+            }
+            seen.push(c);
+            let free = match owner.get(&c) {
+                None => true,
+                Some(&other) => augment(other, candidates, owner, seen),
+            };
+            if free {
+                owner.insert(c, p);
+                return true;
+            }
+        }
+        false
+    }
+    let mut owner = HashMap::new();
+    (0..candidates.len()).all(|p| augment(p, candidates, &mut owner, &mut Vec::new()))
+}
+
+// Row and column of each cell drawn for segments a-g in a 6x7 digit.
+const SEGMENT_CELLS: [&[(usize, usize)]; 7] = [
+    &[(0, 1), (0, 2), (0, 3), (0, 4)],
+    &[(1, 0), (2, 0)],
+    &[(1, 5), (2, 5)],
+    &[(3, 1), (3, 2), (3, 3), (3, 4)],
+    &[(4, 0), (5, 0)],
+    &[(4, 5), (5, 5)],
+    &[(6, 1), (6, 2), (6, 3), (6, 4)],
+];
+
+impl Entry {
+    // Draws the decoded output as seven-segment digits. Each lit segment is
+    // drawn with the letter of the scrambled wire that drives it, and unlit
+    // segments with '.'.
+    pub fn render(&self, glyphs: &Glyphs) -> Result<String> {
+        if glyphs.segments() != 7 {
+            bail!("can only render seven segments; got {}", glyphs.segments());
+        }
+        let wiring = self.wiring_for(glyphs)?;
+        let mut rows = vec![String::new(); 7];
+        for (i, digit) in self.output.iter().enumerate() {
+            glyphs.decode(&wiring, digit)?;
+            let mut cells = [[' '; 6]; 7];
+            for (segment, cell) in SEGMENT_CELLS.iter().enumerate() {
+                let wire = (0..7).find(|&w| wiring.0[w] as usize == segment).unwrap();
+                let wire = (b'a' + wire as u8) as char;
+                let c = if digit.contains(wire) { wire } else { '.' };
+                cell.iter().for_each(|&(r, col)| cells[r][col] = c);
+            }
+            for (row, cells) in rows.iter_mut().zip(cells) {
+                if i > 0 {
+                    row.push_str("  ");
+                }
+                row.extend(cells);
+            }
+        }
+        Ok(rows
+            .into_iter()
+            .map(|r| r.trim_end().to_string() + "\n")
+            .collect())
+    }
+}
+
+// Generator produces random scrambled entries for the decimal display, along
+// with the value each one should decode to. The same seed always produces the
+// same entries.
+pub struct Generator {
+    state: u64,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Generator { state: seed }
+    }
+
+    // SplitMix64, which is plenty for shuffling test inputs.
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // Returns the wires lighting digit d, in random order.
+    fn scramble(&mut self, wires: &[char], d: usize) -> String {
+        let mut pattern = DECIMAL[d]
+            .1
+            .chars()
+            .map(|s| wires[(s as u8 - b'a') as usize])
+            .collect::<Vec<_>>();
+        self.shuffle(&mut pattern);
+        pattern.into_iter().collect()
+    }
+
+    fn shuffle<T>(&mut self, v: &mut [T]) {
+        for i in (1..v.len()).rev() {
+            v.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = (String, i32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // wires[s] is the wire that drives segment s.
+        let mut wires = (b'a'..=b'g').map(char::from).collect::<Vec<_>>();
+        self.shuffle(&mut wires);
+        let mut digits = (0..10).collect::<Vec<_>>();
+        self.shuffle(&mut digits);
+        let patterns = digits
+            .into_iter()
+            .map(|d| self.scramble(&wires, d))
+            .collect::<Vec<_>>();
+        let output = (0..4).map(|_| self.below(10)).collect::<Vec<_>>();
+        let answer = output.iter().fold(0, |acc, &d| acc * 10 + d as i32);
+        let output = output
+            .into_iter()
+            .map(|d| self.scramble(&wires, d))
+            .collect::<Vec<_>>();
+        Some((
+            format!("{} | {}", patterns.join(" "), output.join(" ")),
+            answer,
+        ))
+    }
+}
+
 impl Entries {
     pub fn parse<T: io::BufRead>(r: T) -> Result<Entries> {
         let entries = r
@@ -137,10 +963,11 @@ impl Entries {
     }
 
     pub fn output_sum(&self) -> Result<i32> {
+        let glyphs = Glyphs::decimal();
         Ok(self
             .0
             .par_iter()
-            .map(Entry::output_value)
+            .map(|e| e.output_value_for(&glyphs))
             .collect::<Result<Vec<_>>>()?
             .iter()
             .sum())
@@ -176,4 +1003,216 @@ gcafb gcf dcaebfg ecagb gf abcdeg gaef cafbge fdbac fegbdc | fgae cfgab fg bagce
         let entries = Entries::parse(input).unwrap();
         assert_eq!(61229, entries.output_sum().unwrap());
     }
+
+    #[test]
+    fn test_wiring() {
+        let entry =
+            "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf"
+                .parse::<Entry>()
+                .unwrap();
+        let wiring = entry.wiring().unwrap();
+        assert_eq!("cfgabde", wiring.to_string());
+        assert_eq!(Some('c'), wiring.segment_for('a'));
+        assert_eq!(5353, entry.output_value().unwrap());
+    }
+
+    #[test]
+    fn test_wiring_errors() {
+        // Several five-wire patterns contain both wires of the 1, but only the
+        // 3 lights both of its segments.
+        let contradictory =
+            "ab ab abcd abcde abcdf abcef abcdef abcdeg abcdfg abcdefg | ab ab ab ab"
+                .parse::<Entry>()
+                .unwrap();
+        let err = contradictory.wiring().unwrap_err();
+        assert!(format!("{:#}", err).contains("contradictory"), "{:#}", err);
+        assert!(contradictory.output_value().is_err());
+
+        let ambiguous = "ab ab ab ab ab ab ab ab ab ab | ab ab ab ab"
+            .parse::<Entry>()
+            .unwrap();
+        let err = ambiguous.wiring().unwrap_err();
+        assert!(format!("{:#}", err).contains("ambiguous"), "{:#}", err);
+
+        // A line missing a pattern, as from a corrupted input.
+        let truncated = "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb | cdfeb fcadb"
+            .parse::<Entry>()
+            .unwrap();
+        let err = truncated.output_value().unwrap_err();
+        assert!(format!("{:#}", err).contains("at least 10"), "{:#}", err);
+
+        let bad_wire = "ab ab ab ab ab ab ab ab ab ax | ab ab ab ab"
+            .parse::<Entry>()
+            .unwrap();
+        assert!(bad_wire.wiring().is_err());
+    }
+
+    #[test]
+    fn test_glyph_sets() {
+        for glyphs in [
+            Glyphs::decimal(),
+            Glyphs::hex(),
+            Glyphs::fourteen_segment(),
+            Glyphs::sixteen_segment(),
+        ] {
+            // Scramble the display by reversing the order of its wires.
+            let n = glyphs.segments();
+            let scramble = |m: u32| {
+                let reversed = (0..n)
+                    .filter(|i| m & (1 << i) != 0)
+                    .fold(0, |acc, i| acc | 1 << (n - 1 - i));
+                Digit(reversed).to_string()
+            };
+            let patterns = glyphs
+                .glyphs
+                .iter()
+                .map(|&(_, m)| scramble(m))
+                .collect::<Vec<_>>();
+            let output = glyphs
+                .glyphs
+                .iter()
+                .rev()
+                .map(|&(_, m)| scramble(m))
+                .collect::<Vec<_>>();
+            let entry = format!("{} | {}", patterns.join(" "), output.join(" "))
+                .parse::<Entry>()
+                .unwrap();
+            let want = glyphs
+                .glyphs
+                .iter()
+                .rev()
+                .map(|&(c, _)| c)
+                .collect::<String>();
+            assert_eq!(want, entry.decode(&glyphs).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_custom_glyphs() {
+        assert!(Glyphs::new(3, &[('x', "ab"), ('y', "ba")]).is_err());
+        assert!(Glyphs::new(3, &[('x', "ad")]).is_err());
+
+        // A vendor whose 7 also lights the upper left segment.
+        let table = DECIMAL
+            .iter()
+            .map(|&(c, s)| if c == '7' { (c, "abcf") } else { (c, s) })
+            .collect::<Vec<_>>();
+        let glyphs = Glyphs::new(7, &table).unwrap();
+        let entry = "abcefg cf acdeg acdfg bcdf abdfg abdefg abcf abcdefg abcdfg | abcf cf"
+            .parse::<Entry>()
+            .unwrap();
+        assert_eq!("71", entry.decode(&glyphs).unwrap());
+    }
+
+    #[test]
+    fn test_render() {
+        let entry = "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb"
+            .parse::<Entry>()
+            .unwrap();
+        assert_eq!(
+            " dddd    dddd
+e    .  .    a
+e    .  .    a
+ ffff    ffff
+.    b  .    b
+.    b  .    b
+ cccc    cccc
+",
+            entry.render(&Glyphs::decimal()).unwrap()
+        );
+        assert!(entry.render(&Glyphs::fourteen_segment()).is_err());
+    }
+
+    #[test]
+    fn test_generator() {
+        let generated = Generator::new(2021).take(100).collect::<Vec<_>>();
+        assert_eq!(
+            generated,
+            Generator::new(2021).take(100).collect::<Vec<_>>()
+        );
+        assert_ne!(
+            generated,
+            Generator::new(2022).take(100).collect::<Vec<_>>()
+        );
+        for (line, answer) in generated {
+            let entry = line.parse::<Entry>().unwrap();
+            assert_eq!(answer, entry.output_value().unwrap(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_digit() {
+        let digit = "gcbe".parse::<Digit>().unwrap();
+        assert_eq!("bceg", digit.to_string());
+        assert_eq!(4, digit.len());
+        assert!(digit.contains('g'));
+        assert!(!digit.contains('a'));
+        assert_eq!(2, digit.common(&"cg".parse::<Digit>().unwrap()));
+        assert!("abA".parse::<Digit>().is_err());
+        assert!("aba".parse::<Digit>().is_err());
+
+        let entry = "be cfbegad cbdgef fgaecd cgeb fdcge agebfd fecdb fabcd edb | fdgacbe cefdb cefbgd gcbe"
+            .parse::<Entry>()
+            .unwrap();
+        assert_eq!(
+            "be abcdefg bcdefg acdefg bceg cdefg abdefg bcdef abcdf bde | abcdefg bcdef bcdefg bceg ",
+            entry.to_string()
+        );
+    }
+
+    #[test]
+    fn test_diagnose_healthy() {
+        let entry =
+            "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf"
+                .parse::<Entry>()
+                .unwrap();
+        let diagnosis = entry.diagnose(&Glyphs::decimal()).unwrap();
+        assert_eq!(entry.wiring().unwrap(), diagnosis.wiring);
+        assert!(diagnosis.dead.is_empty());
+        assert!(diagnosis.stuck.is_empty());
+        assert_eq!(
+            vec![('5', 1.0), ('3', 1.0), ('5', 1.0), ('3', 1.0)],
+            diagnosis.output
+        );
+    }
+
+    #[test]
+    fn test_diagnose_faults() {
+        // Wire f drives the middle segment, which is dead, so 8 looks like 0.
+        let entry = "acedgb cdbe gcda bcad dab ceabd cdgeb eab cagedb ab | cdeb acedgb cdbe cdba"
+            .parse::<Entry>()
+            .unwrap();
+        assert!(entry.output_value().is_err());
+        let diagnosis = entry.diagnose(&Glyphs::decimal()).unwrap();
+        assert_eq!("cfgabde", diagnosis.wiring.to_string());
+        assert_eq!(vec!['d'], diagnosis.dead);
+        assert!(diagnosis.stuck.is_empty());
+        assert_eq!('0', diagnosis.output[1].0);
+        assert_eq!(0.5, diagnosis.output[1].1);
+        assert_eq!(('5', 1.0), diagnosis.output[0]);
+
+        // Wire c drives the bottom segment, which is stuck on.
+        let entry = "acedgfb cdfbe gcdfa fbcad dabc cefabd cdfgeb eafbc cagedb abc | cdfeb abc"
+            .parse::<Entry>()
+            .unwrap();
+        let diagnosis = entry.diagnose(&Glyphs::decimal()).unwrap();
+        assert_eq!(vec!['g'], diagnosis.stuck);
+        assert!(diagnosis.dead.is_empty());
+        assert_eq!(('5', 1.0), diagnosis.output[0]);
+        assert_eq!('1', diagnosis.output[1].0);
+
+        assert!(entry.diagnose(&Glyphs::fourteen_segment()).is_err());
+    }
+
+    // Run with: cargo test --release -- --ignored --nocapture bench_output_sum
+    #[test]
+    #[ignore]
+    fn bench_output_sum() {
+        let (lines, answers): (Vec<_>, Vec<_>) = Generator::new(7).take(200_000).unzip();
+        let entries = Entries::parse(testutil::string_reader(&lines.join("\n"))).unwrap();
+        let start = std::time::Instant::now();
+        let sum = entries.output_sum().unwrap();
+        println!("output_sum of 200k entries in {:?}", start.elapsed());
+        assert_eq!(answers.iter().sum::<i32>(), sum);
+    }
 }
@@ -1,147 +1,1027 @@
-use std::collections::VecDeque;
 use std::io;
+use std::ops::{Add, Mul};
 
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
+use num_bigint::BigUint;
 
 use crate::util;
 
-pub struct Line(String);
+// Line is one line of input, with its 1-based line number.
+pub struct Line {
+    number: usize,
+    text: String,
+}
 pub struct Lines(Vec<Line>);
 
-enum ParseResult {
-    IllegalChar(char),
-    CompletionChars(Vec<char>),
+// Checker validates the nesting of a configurable set of bracket pairs.
+// Characters it is told to ignore are skipped, and any other character that
+// isn't a bracket is an error.
+pub struct Checker {
+    pairs: Vec<(char, char)>,
+    ignore: Box<dyn Fn(char) -> bool + Send + Sync>,
+}
+
+// Scoring assigns scores to the closing chars of a checker: a fixed score for
+// an illegal char, and a digit for each completion char, which are combined
+// in the given base. Unknown chars don't affect the scores, so they neither
+// hide a mismatched char later in a line nor keep it from being completed.
+pub struct Scoring {
+    checker: Checker,
+    // The illegal and completion scores of each of the checker's pairs.
+    scores: Vec<(u64, u64)>,
+    base: u64,
+}
+
+// Columns are 1-based character positions within a line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyntaxError {
+    // A closing char that doesn't match the innermost open char, which is
+    // None if nothing is open.
+    Mismatched {
+        column: usize,
+        found: char,
+        expected: Option<char>,
+        opened_at: Option<usize>,
+    },
+    Unknown {
+        column: usize,
+        found: char,
+    },
+}
+
+// Diagnostic is a problem found in a line, which displays like a compiler
+// error with the offending columns underlined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub source: String,
+    pub kind: DiagnosticKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    Error(SyntaxError),
+    // A line without errors that leaves chars open, with the chars that would
+    // close them.
+    Incomplete {
+        unclosed: Vec<(usize, char)>,
+        completion: String,
+    },
+}
+
+// Edit is a single change to a line, at a 1-based column of the original
+// line. Inserted chars go before the char at that column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    Insert { column: usize, c: char },
+    Delete { column: usize },
+    Replace { column: usize, c: char },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repair {
+    pub edits: Vec<Edit>,
+    pub repaired: String,
+}
+
+// Fault is a syntax error found by Checker::step, without its location.
+enum Fault<P> {
+    Mismatched {
+        found: char,
+        expected: Option<char>,
+        opened_at: Option<P>,
+    },
+    Unknown {
+        found: char,
+    },
+}
+
+// Position is a 1-based line and column within a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+// StreamError is like SyntaxError, but located by Position since brackets in a
+// stream may be closed on a later line than they were opened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamError {
+    Mismatched {
+        at: Position,
+        found: char,
+        expected: Option<char>,
+        opened_at: Option<Position>,
+    },
+    // Includes bytes that aren't valid UTF-8, which are reported as U+FFFD.
+    Unknown {
+        at: Position,
+        found: char,
+    },
+}
+
+// Stream checks input that is pushed to it in chunks of bytes, which may split
+// lines or even UTF-8 sequences. Newlines don't reset nesting, and only the
+// stack of open chars is kept, so the input can be arbitrarily large.
+pub struct Stream<'a> {
+    checker: &'a Checker,
+    stack: Vec<(Position, char)>,
+    // The position of the next char.
+    at: Position,
+    // The start of a UTF-8 sequence split across chunks.
+    partial: Vec<u8>,
+}
+
+// StreamEnd is what's left when a stream is finished: the chars still open,
+// outermost first, and the closing chars that would complete the stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamEnd {
+    pub errors: Vec<StreamError>,
+    pub unclosed: Vec<(Position, char)>,
+    pub completion: String,
+}
+
+// Check is the result of checking a line: every error, and the chars still
+// open at the end of the line with their columns, innermost last.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub errors: Vec<SyntaxError>,
+    pub unclosed: Vec<(usize, char)>,
 }
 
 impl Lines {
     pub fn parse<T: io::BufRead>(r: T) -> Result<Lines> {
         Ok(Lines(
             r.lines()
-                .filter(util::remove_empty_lines)
-                .map(|l| l.context("failed to read line").map(Line::new))
+                .enumerate()
+                .filter(|(_, l)| util::remove_empty_lines(l))
+                .map(|(i, l)| {
+                    l.context("failed to read line")
+                        .map(|text| Line::new(i + 1, text))
+                })
                 .collect::<Result<Vec<_>>>()?,
         ))
     }
 
-    pub fn syntax_error_score(&self) -> i32 {
+    pub fn diagnostics(&self, checker: &Checker) -> Vec<Diagnostic> {
+        self.0.iter().flat_map(|l| l.diagnostics(checker)).collect()
+    }
+
+    // Returns the diagnostics as a JSON array, for editor tooling.
+    pub fn diagnostics_json(&self, checker: &Checker) -> String {
+        let diagnostics = self
+            .diagnostics(checker)
+            .iter()
+            .map(Diagnostic::to_json)
+            .collect::<Vec<_>>();
+        format!("[{}]", diagnostics.join(","))
+    }
+
+    pub fn syntax_error_score(&self) -> u64 {
+        self.syntax_error_score_with(&Scoring::default())
+    }
+
+    pub fn middle_completion_score(&self) -> Option<BigUint> {
+        self.middle_completion_score_with(&Scoring::default())
+    }
+
+    // Sums the score of the first illegal char of each corrupted line.
+    pub fn syntax_error_score_with(&self, scoring: &Scoring) -> u64 {
         self.0
             .iter()
-            .filter_map(|l| match l.parse_result() {
-                ParseResult::IllegalChar(c) => Some(c),
-                _ => None,
+            .filter_map(|l| {
+                l.check(&scoring.checker)
+                    .errors
+                    .iter()
+                    .find_map(|e| match e {
+                        SyntaxError::Mismatched { found, .. } => {
+                            Some(scoring.illegal_score(*found))
+                        }
+                        SyntaxError::Unknown { .. } => None,
+                    })
             })
-            .map(Line::illegal_char_score)
             .sum()
     }
 
-    pub fn middle_completion_score(&self) -> i64 {
-        let mut completion_scores = self
-            .0
+    // Returns the completion score of each incomplete line. T is typically
+    // u64, num_bigint::BigUint or modular::Modular, since the score grows
+    // exponentially with the depth of the line.
+    pub fn completion_scores<T>(&self, scoring: &Scoring) -> Vec<T>
+    where
+        T: Clone + From<u64> + Add<Output = T> + Mul<Output = T>,
+    {
+        self.0
             .iter()
-            .filter_map(|l| match l.parse_result() {
-                ParseResult::CompletionChars(c) => Some(c),
-                _ => None,
+            .filter_map(|l| {
+                let check = l.check(&scoring.checker);
+                let corrupted = check
+                    .errors
+                    .iter()
+                    .any(|e| matches!(e, SyntaxError::Mismatched { .. }));
+                if !corrupted && !check.unclosed.is_empty() {
+                    Some(scoring.completion_score(&check.unclosed))
+                } else {
+                    None
+                }
             })
-            .map(|chars| chars.into_iter().fold(0, Line::completion_chars_score))
-            .collect::<Vec<_>>();
-        completion_scores.sort_unstable();
-        completion_scores[completion_scores.len() / 2]
+            .collect()
+    }
+
+    // Returns the median completion score, or None if no line is incomplete.
+    // With an even number of incomplete lines the lower of the two middle
+    // scores is used.
+    pub fn middle_completion_score_with<T>(&self, scoring: &Scoring) -> Option<T>
+    where
+        T: Ord + Clone + From<u64> + Add<Output = T> + Mul<Output = T>,
+    {
+        let mut completion_scores = self.completion_scores::<T>(scoring);
+        if completion_scores.is_empty() {
+            return None;
+        }
+        let middle = (completion_scores.len() - 1) / 2;
+        completion_scores.select_nth_unstable(middle);
+        Some(completion_scores.swap_remove(middle))
     }
 }
 
 impl Line {
-    pub fn new(s: String) -> Self {
-        Self(s)
+    pub fn new(number: usize, text: String) -> Self {
+        Self { number, text }
     }
 
-    fn parse_result(&self) -> ParseResult {
-        let mut stack = VecDeque::new();
-        let first_illegal = self
-            .0
-            .chars()
-            .filter_map(|c| match c {
-                '(' | '{' | '[' | '<' => {
-                    stack.push_front(c);
-                    None
+    pub fn check(&self, checker: &Checker) -> Check {
+        checker.check(&self.text)
+    }
+
+    pub fn repair(&self, checker: &Checker) -> Result<Option<Repair>> {
+        checker.repair(&self.text)
+    }
+
+    // Returns a diagnostic for each error in the line, or one for the unclosed
+    // chars if the line is only incomplete.
+    pub fn diagnostics(&self, checker: &Checker) -> Vec<Diagnostic> {
+        let check = self.check(checker);
+        let kinds = if check.errors.is_empty() && !check.unclosed.is_empty() {
+            let completion = check
+                .unclosed
+                .iter()
+                .rev()
+                .filter_map(|&(_, c)| checker.closing_for(c))
+                .collect();
+            vec![DiagnosticKind::Incomplete {
+                unclosed: check.unclosed,
+                completion,
+            }]
+        } else {
+            check
+                .errors
+                .into_iter()
+                .map(DiagnosticKind::Error)
+                .collect()
+        };
+        kinds
+            .into_iter()
+            .map(|kind| Diagnostic {
+                line: self.number,
+                source: self.text.clone(),
+                kind,
+            })
+            .collect()
+    }
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Checker::new(&[('(', ')'), ('[', ']'), ('{', '}'), ('<', '>')]).unwrap()
+    }
+}
+
+impl Default for Scoring {
+    fn default() -> Self {
+        Scoring::new(
+            Checker::default(),
+            &[(')', 3), (']', 57), ('}', 1197), ('>', 25137)],
+            &[(')', 1), (']', 2), ('}', 3), ('>', 4)],
+            5,
+        )
+        .unwrap()
+    }
+}
+
+impl Scoring {
+    // Every closing char of the checker needs exactly one illegal and one
+    // completion score, and every score must be for one of its closing chars.
+    pub fn new(
+        checker: Checker,
+        illegal: &[(char, u64)],
+        completion: &[(char, u64)],
+        base: u64,
+    ) -> Result<Scoring> {
+        for (kind, table) in [("illegal", illegal), ("completion", completion)] {
+            if let Some(&(c, _)) = table
+                .iter()
+                .find(|&&(c, _)| checker.opening_for(c).is_none())
+            {
+                bail!("{} char score for {:?}, which closes no pair", kind, c);
+            }
+            for &(_, close) in &checker.pairs {
+                match table.iter().filter(|&&(c, _)| c == close).count() {
+                    0 => bail!("no {} char score for {:?}", kind, close),
+                    1 => {}
+                    _ => bail!("more than one {} char score for {:?}", kind, close),
                 }
-                '}' => {
-                    if Some('{') == stack.pop_front() {
-                        None
-                    } else {
-                        Some(c)
-                    }
+            }
+        }
+        let score =
+            |table: &[(char, u64)], close| table.iter().find(|&&(c, _)| c == close).unwrap().1;
+        let scores = checker
+            .pairs
+            .iter()
+            .map(|&(_, close)| (score(illegal, close), score(completion, close)))
+            .collect();
+        Ok(Scoring {
+            checker,
+            scores,
+            base,
+        })
+    }
+
+    pub fn checker(&self) -> &Checker {
+        &self.checker
+    }
+
+    // Mismatched chars always close one of the checker's pairs.
+    fn illegal_score(&self, close: char) -> u64 {
+        let pair = self.checker.pairs.iter().position(|p| p.1 == close);
+        self.scores[pair.expect("not a closing char")].0
+    }
+
+    fn completion_score<T>(&self, unclosed: &[(usize, char)]) -> T
+    where
+        T: Clone + From<u64> + Add<Output = T> + Mul<Output = T>,
+    {
+        unclosed.iter().rev().fold(T::from(0), |acc, &(_, open)| {
+            let pair = self.checker.pairs.iter().position(|p| p.0 == open);
+            let score = self.scores[pair.expect("not an opening char")].1;
+            acc * T::from(self.base) + T::from(score)
+        })
+    }
+}
+
+impl Checker {
+    pub fn new(pairs: &[(char, char)]) -> Result<Checker> {
+        let mut seen = Vec::new();
+        for &(open, close) in pairs {
+            for c in [open, close] {
+                if seen.contains(&c) {
+                    bail!("{:?} is used by more than one bracket", c);
                 }
-                ')' => {
-                    if Some('(') == stack.pop_front() {
-                        None
-                    } else {
-                        Some(c)
+                seen.push(c);
+            }
+        }
+        Ok(Checker {
+            pairs: pairs.to_vec(),
+            ignore: Box::new(|_| false),
+        })
+    }
+
+    // Skips characters matching f, such as letters or whitespace, instead of
+    // reporting them as errors.
+    pub fn ignoring(mut self, f: impl Fn(char) -> bool + Send + Sync + 'static) -> Self {
+        self.ignore = Box::new(f);
+        self
+    }
+
+    pub fn closing_for(&self, open: char) -> Option<char> {
+        self.pairs.iter().find(|p| p.0 == open).map(|p| p.1)
+    }
+
+    fn opening_for(&self, close: char) -> Option<char> {
+        self.pairs.iter().find(|p| p.1 == close).map(|p| p.0)
+    }
+
+    // Checks a whole line. After a mismatched closing char the checker carries
+    // on as if it wasn't there, so later errors are reported too.
+    pub fn check(&self, line: &str) -> Check {
+        let mut stack = Vec::new();
+        let errors = line
+            .chars()
+            .enumerate()
+            .filter_map(|(i, c)| {
+                self.step(&mut stack, i + 1, c).map(|fault| match fault {
+                    Fault::Mismatched {
+                        found,
+                        expected,
+                        opened_at,
+                    } => SyntaxError::Mismatched {
+                        column: i + 1,
+                        found,
+                        expected,
+                        opened_at,
+                    },
+                    Fault::Unknown { found } => SyntaxError::Unknown {
+                        column: i + 1,
+                        found,
+                    },
+                })
+            })
+            .collect();
+        Check {
+            errors,
+            unclosed: stack,
+        }
+    }
+
+    // Processes one char, given the stack of open chars and where they were
+    // opened, which is a column for a line or a Position for a stream.
+    fn step<P: Copy>(&self, stack: &mut Vec<(P, char)>, at: P, c: char) -> Option<Fault<P>> {
+        if self.closing_for(c).is_some() {
+            stack.push((at, c));
+            None
+        } else if self.opening_for(c).is_some() {
+            let innermost = stack.last().copied();
+            if innermost.map(|(_, open)| open) == self.opening_for(c) {
+                stack.pop();
+                None
+            } else {
+                Some(Fault::Mismatched {
+                    found: c,
+                    expected: innermost.and_then(|(_, open)| self.closing_for(open)),
+                    opened_at: innermost.map(|(at, _)| at),
+                })
+            }
+        } else if (self.ignore)(c) {
+            None
+        } else {
+            Some(Fault::Unknown { found: c })
+        }
+    }
+}
+
+// The most chars, not counting ignored ones, that Checker::repair accepts.
+// Its tables grow with the square of the length and its time with the cube.
+pub const MAX_REPAIR_LEN: usize = 500;
+
+// How a balanced run of chars is made from its first char.
+#[derive(Clone, Copy)]
+enum Balance {
+    Empty,
+    Delete,
+    // Pair with the char at this index, using this (open, close) pair.
+    Pair(usize, (char, char)),
+    // Close with an inserted char placed before the char at this index.
+    Insert(usize),
+}
+
+impl Checker {
+    // Proposes the fewest edits that leave a corrupted line without errors.
+    // The result may still be incomplete, to be finished by the usual
+    // completion. Returns None if the line has no errors to repair.
+    //
+    // A line without errors is a sequence of balanced runs and unclosed
+    // openers, so this finds the edit distance to that shape: cost[i][j] is
+    // the fewest edits making chars i..j balanced, tried by deleting the first
+    // char, pairing it with a later char (replacing either if needed), or
+    // closing it with an inserted char. Ignored chars are left alone. Lines
+    // longer than MAX_REPAIR_LEN are rejected, since this takes cubic time.
+    pub fn repair(&self, line: &str) -> Result<Option<Repair>> {
+        if self.check(line).errors.is_empty() {
+            return Ok(None);
+        }
+        let chars = line
+            .chars()
+            .enumerate()
+            .filter(|&(_, c)| !(self.ignore)(c))
+            .collect::<Vec<_>>();
+        let n = chars.len();
+        if n > MAX_REPAIR_LEN {
+            bail!(
+                "line has {} chars to repair; at most {} are supported",
+                n,
+                MAX_REPAIR_LEN
+            );
+        }
+        let mut cost = vec![vec![0; n + 1]; n + 1];
+        let mut how = vec![vec![Balance::Empty; n + 1]; n + 1];
+        for len in 1..=n {
+            for i in 0..=n - len {
+                let j = i + len;
+                let mut best = (usize::MAX, Balance::Empty);
+                let first = chars[i].1;
+                for k in i + 1..j {
+                    let (pair_cost, pair) = self.pair_cost(first, chars[k].1);
+                    let c = pair_cost + cost[i + 1][k] + cost[k + 1][j];
+                    if c < best.0 {
+                        best = (c, Balance::Pair(k, pair));
                     }
                 }
-                ']' => {
-                    if Some('[') == stack.pop_front() {
-                        None
-                    } else {
-                        Some(c)
+                if self.closing_for(first).is_some() {
+                    let (c, k) = (i + 1..=j)
+                        .map(|k| (1 + cost[i + 1][k] + cost[k][j], k))
+                        .min()
+                        .unwrap();
+                    if c < best.0 {
+                        best = (c, Balance::Insert(k));
                     }
                 }
-                '>' => {
-                    if Some('<') == stack.pop_front() {
-                        None
-                    } else {
-                        Some(c)
-                    }
+                if 1 + cost[i + 1][j] < best.0 {
+                    best = (1 + cost[i + 1][j], Balance::Delete);
                 }
-                _ => panic!("unexpected char: {}", c),
-            })
-            .next();
-        match first_illegal {
-            Some(c) => ParseResult::IllegalChar(c),
-            None => ParseResult::CompletionChars(
-                stack
-                    .into_iter()
-                    .map(Line::completion_char_for)
-                    .collect::<Vec<_>>(),
+                cost[i][j] = best.0;
+                how[i][j] = best.1;
+            }
+        }
+
+        // prefix[j] is the fewest edits leaving the first j chars without
+        // errors, and start[j] where its last balanced run starts, or None if
+        // char j - 1 is left as an unclosed opener.
+        let mut prefix = vec![0; n + 1];
+        let mut start = vec![None; n + 1];
+        for j in 1..=n {
+            let mut best = (usize::MAX, None);
+            if self.closing_for(chars[j - 1].1).is_some() {
+                best = (prefix[j - 1], None);
+            }
+            for i in 0..j {
+                let c = prefix[i] + cost[i][j];
+                if c < best.0 {
+                    best = (c, Some(i));
+                }
+            }
+            prefix[j] = best.0;
+            start[j] = best.1;
+        }
+
+        let mut edits = Vec::new();
+        let mut j = n;
+        while j > 0 {
+            match start[j] {
+                Some(i) => {
+                    self.collect_edits(&chars, &how, i, j, line.chars().count(), &mut edits);
+                    j = i;
+                }
+                None => j -= 1,
+            }
+        }
+        edits.sort_by_key(|e| match e {
+            Edit::Insert { column, .. } => (*column, 0),
+            Edit::Delete { column } | Edit::Replace { column, .. } => (*column, 1),
+        });
+        Ok(Some(Repair {
+            repaired: apply(line, &edits),
+            edits,
+        }))
+    }
+
+    // Returns the fewest replacements that make open and close a pair, and
+    // the pair they become.
+    fn pair_cost(&self, open: char, close: char) -> (usize, (char, char)) {
+        self.pairs
+            .iter()
+            .map(|&(o, c)| ((o != open) as usize + (c != close) as usize, (o, c)))
+            .min_by_key(|&(cost, _)| cost)
+            .unwrap()
+    }
+
+    fn collect_edits(
+        &self,
+        chars: &[(usize, char)],
+        how: &[Vec<Balance>],
+        i: usize,
+        j: usize,
+        line_len: usize,
+        edits: &mut Vec<Edit>,
+    ) {
+        if i >= j {
+            return;
+        }
+        let (index, first) = chars[i];
+        match how[i][j] {
+            Balance::Empty => {}
+            Balance::Delete => {
+                edits.push(Edit::Delete { column: index + 1 });
+                self.collect_edits(chars, how, i + 1, j, line_len, edits);
+            }
+            Balance::Pair(k, (open, close)) => {
+                if first != open {
+                    edits.push(Edit::Replace {
+                        column: index + 1,
+                        c: open,
+                    });
+                }
+                if chars[k].1 != close {
+                    edits.push(Edit::Replace {
+                        column: chars[k].0 + 1,
+                        c: close,
+                    });
+                }
+                self.collect_edits(chars, how, i + 1, k, line_len, edits);
+                self.collect_edits(chars, how, k + 1, j, line_len, edits);
+            }
+            Balance::Insert(k) => {
+                let column = chars.get(k).map_or(line_len + 1, |c| c.0 + 1);
+                edits.push(Edit::Insert {
+                    column,
+                    c: self.closing_for(first).unwrap(),
+                });
+                self.collect_edits(chars, how, i + 1, k, line_len, edits);
+                self.collect_edits(chars, how, k, j, line_len, edits);
+            }
+        }
+    }
+}
+
+fn apply(line: &str, edits: &[Edit]) -> String {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut repaired = String::new();
+    for column in 1..=chars.len() + 1 {
+        for e in edits {
+            match *e {
+                Edit::Insert { column: at, c } if at == column => repaired.push(c),
+                _ => {}
+            }
+        }
+        let c = match chars.get(column - 1) {
+            Some(&c) => c,
+            None => break,
+        };
+        match edits.iter().find(|e| match e {
+            Edit::Delete { column: at } | Edit::Replace { column: at, .. } => *at == column,
+            Edit::Insert { .. } => false,
+        }) {
+            Some(Edit::Delete { .. }) => {}
+            Some(Edit::Replace { c, .. }) => repaired.push(*c),
+            _ => repaired.push(c),
+        }
+    }
+    repaired
+}
+
+impl Checker {
+    pub fn stream(&self) -> Stream<'_> {
+        Stream {
+            checker: self,
+            stack: Vec::new(),
+            at: Position { line: 1, column: 1 },
+            partial: Vec::new(),
+        }
+    }
+
+    // Checks everything read from r, passing errors to on_error as they are
+    // found.
+    pub fn check_reader<R: io::Read>(
+        &self,
+        mut r: R,
+        mut on_error: impl FnMut(StreamError),
+    ) -> Result<StreamEnd> {
+        let mut stream = self.stream();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = match r.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e).context("failed to read stream"),
+            };
+            stream.feed(&buf[..n]).into_iter().for_each(&mut on_error);
+        }
+        Ok(stream.finish())
+    }
+}
+
+impl Stream<'_> {
+    // Checks the next chunk of input, returning the errors found in it.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<StreamError> {
+        let mut errors = Vec::new();
+        let mut rest = chunk;
+        while !self.partial.is_empty() && !rest.is_empty() {
+            self.partial.push(rest[0]);
+            match std::str::from_utf8(&self.partial) {
+                Ok(s) => {
+                    let c = s.chars().next().unwrap();
+                    self.push(c, &mut errors);
+                    self.partial.clear();
+                    rest = &rest[1..];
+                }
+                Err(e) if e.error_len().is_none() => rest = &rest[1..],
+                Err(_) => {
+                    // The new byte doesn't continue the sequence, so it starts
+                    // the next char.
+                    self.push(char::REPLACEMENT_CHARACTER, &mut errors);
+                    self.partial.clear();
+                }
+            }
+        }
+        while !rest.is_empty() {
+            let (valid, after) = match std::str::from_utf8(rest) {
+                Ok(s) => (s, &rest[rest.len()..]),
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    (std::str::from_utf8(valid).unwrap(), after)
+                }
+            };
+            valid.chars().for_each(|c| self.push(c, &mut errors));
+            rest = match std::str::from_utf8(after) {
+                Ok(_) => after,
+                Err(e) => match e.error_len() {
+                    Some(n) => {
+                        self.push(char::REPLACEMENT_CHARACTER, &mut errors);
+                        &after[n..]
+                    }
+                    None => {
+                        self.partial.extend_from_slice(after);
+                        &after[after.len()..]
+                    }
+                },
+            };
+        }
+        errors
+    }
+
+    fn push(&mut self, c: char, errors: &mut Vec<StreamError>) {
+        if c == '\n' {
+            self.at.line += 1;
+            self.at.column = 1;
+            return;
+        }
+        let at = self.at;
+        if let Some(fault) = self.checker.step(&mut self.stack, at, c) {
+            errors.push(match fault {
+                Fault::Mismatched {
+                    found,
+                    expected,
+                    opened_at,
+                } => StreamError::Mismatched {
+                    at,
+                    found,
+                    expected,
+                    opened_at,
+                },
+                Fault::Unknown { found } => StreamError::Unknown { at, found },
+            });
+        }
+        self.at.column += 1;
+    }
+
+    pub fn finish(mut self) -> StreamEnd {
+        let mut errors = Vec::new();
+        if !self.partial.is_empty() {
+            self.push(char::REPLACEMENT_CHARACTER, &mut errors);
+        }
+        let completion = self
+            .stack
+            .iter()
+            .rev()
+            .filter_map(|&(_, c)| self.checker.closing_for(c))
+            .collect();
+        StreamEnd {
+            errors,
+            unclosed: self.stack,
+            completion,
+        }
+    }
+}
+
+impl StreamError {
+    pub fn at(&self) -> Position {
+        match self {
+            StreamError::Mismatched { at, .. } | StreamError::Unknown { at, .. } => *at,
+        }
+    }
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl std::fmt::Display for StreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamError::Mismatched {
+                at,
+                found,
+                expected: Some(expected),
+                opened_at: Some(opened_at),
+            } => write!(
+                f,
+                "{}: expected {:?} to close {}, found {:?}",
+                at, expected, opened_at, found
             ),
+            StreamError::Mismatched { at, found, .. } => {
+                write!(f, "{}: unexpected {:?} with nothing open", at, found)
+            }
+            StreamError::Unknown { at, found } => {
+                write!(f, "{}: unknown char {:?}", at, found)
+            }
         }
     }
+}
 
-    fn illegal_char_score(c: char) -> i32 {
-        match c {
-            ')' => 3,
-            ']' => 57,
-            '}' => 1197,
-            '>' => 25137,
-            _ => panic!("unexpected illegal char: {}", c),
+impl Diagnostic {
+    pub fn severity(&self) -> &'static str {
+        match self.kind {
+            DiagnosticKind::Error(_) => "error",
+            DiagnosticKind::Incomplete { .. } => "warning",
         }
     }
 
-    fn completion_chars_score(acc: i64, c: char) -> i64 {
-        acc * 5
-            + match c {
-                ')' => 1,
-                ']' => 2,
-                '}' => 3,
-                '>' => 4,
-                _ => panic!("unexpected completion char: {}", c),
+    pub fn column(&self) -> usize {
+        match &self.kind {
+            DiagnosticKind::Error(e) => e.column(),
+            DiagnosticKind::Incomplete { unclosed, .. } => unclosed[0].0,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match &self.kind {
+            DiagnosticKind::Error(SyntaxError::Mismatched {
+                found,
+                expected: Some(expected),
+                ..
+            }) => format!(
+                "mismatched closing delimiter: expected `{}`, found `{}`",
+                expected, found
+            ),
+            DiagnosticKind::Error(SyntaxError::Mismatched {
+                found,
+                expected: None,
+                ..
+            }) => format!("unexpected closing delimiter: `{}`", found),
+            DiagnosticKind::Error(SyntaxError::Unknown { found, .. }) => {
+                format!("unknown character: `{}`", found)
             }
+            DiagnosticKind::Incomplete { unclosed, .. } => {
+                format!("{} unclosed delimiter(s)", unclosed.len())
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut fields = vec![
+            format!("\"line\":{}", self.line),
+            format!("\"column\":{}", self.column()),
+            format!("\"severity\":{}", json_string(self.severity())),
+            format!("\"message\":{}", json_string(&self.message())),
+        ];
+        match &self.kind {
+            DiagnosticKind::Error(SyntaxError::Mismatched {
+                found,
+                expected,
+                opened_at,
+                ..
+            }) => {
+                fields.push("\"kind\":\"mismatched\"".to_string());
+                fields.push(format!("\"found\":{}", json_string(&found.to_string())));
+                fields.push(format!(
+                    "\"expected\":{}",
+                    expected.map_or("null".to_string(), |c| json_string(&c.to_string()))
+                ));
+                fields.push(format!(
+                    "\"opened_at\":{}",
+                    opened_at.map_or("null".to_string(), |c| c.to_string())
+                ));
+            }
+            DiagnosticKind::Error(SyntaxError::Unknown { found, .. }) => {
+                fields.push("\"kind\":\"unknown\"".to_string());
+                fields.push(format!("\"found\":{}", json_string(&found.to_string())));
+            }
+            DiagnosticKind::Incomplete {
+                unclosed,
+                completion,
+            } => {
+                fields.push("\"kind\":\"incomplete\"".to_string());
+                let columns = unclosed
+                    .iter()
+                    .map(|(col, _)| col.to_string())
+                    .collect::<Vec<_>>();
+                fields.push(format!("\"unclosed\":[{}]", columns.join(",")));
+                fields.push(format!("\"completion\":{}", json_string(completion)));
+            }
+        }
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+// Renders in the style of rustc:
+//
+// error: mismatched closing delimiter: expected `]`, found `}`
+//  --> line 3:13
+//   |
+// 3 | {([(<{}[<>[]}>{[]{[(<()>
+//   |        -    ^ expected `]`
+//   |        |
+//   |        unclosed delimiter
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        writeln!(f, "{}: {}", self.severity(), self.message())?;
+        writeln!(f, "{}--> line {}:{}", gutter, self.line, self.column())?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.source)?;
+        let mut marks = vec![' '; self.source.chars().count() + 1];
+        let (label, opener) = match &self.kind {
+            DiagnosticKind::Error(SyntaxError::Mismatched {
+                column,
+                expected,
+                opened_at,
+                ..
+            }) => {
+                marks[column - 1] = '^';
+                let label = match expected {
+                    Some(c) => format!("expected `{}`", c),
+                    None => "nothing is open".to_string(),
+                };
+                (label, *opened_at)
+            }
+            DiagnosticKind::Error(SyntaxError::Unknown { column, .. }) => {
+                marks[column - 1] = '^';
+                ("not a delimiter".to_string(), None)
+            }
+            DiagnosticKind::Incomplete { unclosed, .. } => {
+                unclosed.iter().for_each(|&(col, _)| marks[col - 1] = '-');
+                ("unclosed delimiters".to_string(), None)
+            }
+        };
+        if let Some(col) = opener {
+            marks[col - 1] = '-';
+        }
+        let marks = marks.into_iter().collect::<String>();
+        writeln!(f, "{} | {} {}", gutter, marks.trim_end(), label)?;
+        if let Some(col) = opener {
+            let indent = " ".repeat(col - 1);
+            writeln!(f, "{} | {}|", gutter, indent)?;
+            writeln!(f, "{} | {}unclosed delimiter", gutter, indent)?;
+        }
+        if let DiagnosticKind::Incomplete { completion, .. } = &self.kind {
+            writeln!(f, "{} |", gutter)?;
+            writeln!(f, "{} = help: complete with `{}`", gutter, completion)?;
+        }
+        Ok(())
     }
+}
 
-    fn completion_char_for(c: char) -> char {
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
         match c {
-            '(' => ')',
-            '[' => ']',
-            '<' => '>',
-            '{' => '}',
-            _ => panic!("unexpected opening char: {}", c),
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
         }
     }
+    out.push('"');
+    out
 }
 
-#[cfg(test)]
+impl SyntaxError {
+    pub fn column(&self) -> usize {
+        match self {
+            SyntaxError::Mismatched { column, .. } | SyntaxError::Unknown { column, .. } => *column,
+        }
+    }
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyntaxError::Mismatched {
+                column,
+                found,
+                expected: Some(expected),
+                ..
+            } => write!(
+                f,
+                "column {}: expected {:?}, found {:?}",
+                column, expected, found
+            ),
+            SyntaxError::Mismatched {
+                column,
+                found,
+                expected: None,
+                ..
+            } => write!(
+                f,
+                "column {}: unexpected {:?} with nothing open",
+                column, found
+            ),
+            SyntaxError::Unknown { column, found } => {
+                write!(f, "column {}: unknown char {:?}", column, found)
+            }
+        }
+    }
+}
 
+#[cfg(test)]
 mod tests {
+    use crate::modular::Modular;
     use crate::testutil;
 
     use super::*;
@@ -166,6 +1046,273 @@ mod tests {
     #[test]
     fn test_middle_completion_score() {
         let lines = Lines::parse(testutil::string_reader(INPUT)).unwrap();
-        assert_eq!(288957, lines.middle_completion_score());
+        assert_eq!(
+            Some(BigUint::from(288957u32)),
+            lines.middle_completion_score()
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let checker = Checker::default();
+        let check = checker.check("{([(<{}[<>[]}>{[]{[(<()>");
+        assert_eq!(
+            SyntaxError::Mismatched {
+                column: 13,
+                found: '}',
+                expected: Some(']'),
+                opened_at: Some(8),
+            },
+            check.errors[0]
+        );
+        assert_eq!(2, check.errors.len());
+
+        let check = checker.check("[({(<(())[]>[[{[]{<()<>>");
+        assert!(check.errors.is_empty());
+        assert_eq!((1, '['), check.unclosed[0]);
+
+        let check = checker.check(")a");
+        assert_eq!(
+            vec![
+                SyntaxError::Mismatched {
+                    column: 1,
+                    found: ')',
+                    expected: None,
+                    opened_at: None,
+                },
+                SyntaxError::Unknown {
+                    column: 2,
+                    found: 'a'
+                },
+            ],
+            check.errors
+        );
+    }
+
+    #[test]
+    fn test_custom_checker() {
+        assert!(Checker::new(&[('(', ')'), ('[', ')')]).is_err());
+        assert!(Checker::new(&[('|', '|')]).is_err());
+
+        let checker = Checker::new(&[('«', '»'), ('(', ')')])
+            .unwrap()
+            .ignoring(|c| c.is_alphanumeric() || c.is_whitespace());
+        let check = checker.check("«a (b c» d) «");
+        assert_eq!(
+            vec![SyntaxError::Mismatched {
+                column: 8,
+                found: '»',
+                expected: Some(')'),
+                opened_at: Some(4),
+            },],
+            check.errors
+        );
+        assert_eq!(vec![(1, '«'), (13, '«')], check.unclosed);
+    }
+
+    #[test]
+    fn test_repair() {
+        let checker = Checker::default();
+        assert_eq!(None, checker.repair("[({(<(())[]>[[{[]{<()<>>").unwrap());
+        assert_eq!(
+            Some(Repair {
+                edits: vec![Edit::Replace { column: 2, c: ')' }],
+                repaired: "()".to_string(),
+            }),
+            checker.repair("(]").unwrap()
+        );
+        assert_eq!(
+            Some(Repair {
+                edits: vec![Edit::Insert { column: 3, c: ')' }],
+                repaired: "{()}".to_string(),
+            }),
+            checker.repair("{(}").unwrap()
+        );
+        assert_eq!(
+            Some(Repair {
+                edits: vec![Edit::Delete { column: 1 }],
+                repaired: "()".to_string(),
+            }),
+            checker.repair("]()").unwrap()
+        );
+
+        let lines = Lines::parse(testutil::string_reader(INPUT)).unwrap();
+        for line in &lines.0 {
+            let check = line.check(&checker);
+            match line.repair(&checker).unwrap() {
+                None => assert!(check.errors.is_empty()),
+                Some(repair) => {
+                    assert_eq!(1, repair.edits.len(), "{}", line.text);
+                    assert!(checker.check(&repair.repaired).errors.is_empty());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_repair_ignored_and_unknown() {
+        let checker = Checker::default().ignoring(char::is_whitespace);
+        let repair = checker.repair("( x ]").unwrap().unwrap();
+        assert_eq!(vec![Edit::Replace { column: 3, c: '[' }], repair.edits);
+        assert_eq!("( [ ]", repair.repaired);
+        let line = Line::new(1, "( x ]".to_string());
+        assert_eq!(Some(repair), line.repair(&checker).unwrap());
+
+        let long = format!("{}]", "(".repeat(MAX_REPAIR_LEN));
+        assert!(checker.repair(&long).is_err());
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let lines = Lines::parse(testutil::string_reader(INPUT)).unwrap();
+        let diagnostics = lines.diagnostics(&Checker::default());
+        assert_eq!(
+            "error: mismatched closing delimiter: expected `]`, found `}`
+ --> line 3:13
+  |
+3 | {([(<{}[<>[]}>{[]{[(<()>
+  |        -    ^ expected `]`
+  |        |
+  |        unclosed delimiter
+",
+            diagnostics
+                .iter()
+                .find(|d| d.line == 3)
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            "warning: 8 unclosed delimiter(s)
+ --> line 1:1
+  |
+1 | [({(<(())[]>[[{[]{<()<>>
+  | ----        ---  - unclosed delimiters
+  |
+  = help: complete with `}}]])})]`
+",
+            diagnostics[0].to_string()
+        );
+    }
+
+    #[test]
+    fn test_diagnostics_json() {
+        let input = "\n)\n(a\n(\n";
+        let lines = Lines::parse(testutil::string_reader(input)).unwrap();
+        assert_eq!(
+            concat!(
+                "[{\"line\":2,\"column\":1,\"severity\":\"error\",",
+                "\"message\":\"unexpected closing delimiter: `)`\",",
+                "\"kind\":\"mismatched\",\"found\":\")\",\"expected\":null,\"opened_at\":null},",
+                "{\"line\":3,\"column\":2,\"severity\":\"error\",",
+                "\"message\":\"unknown character: `a`\",\"kind\":\"unknown\",\"found\":\"a\"},",
+                "{\"line\":4,\"column\":1,\"severity\":\"warning\",",
+                "\"message\":\"1 unclosed delimiter(s)\",\"kind\":\"incomplete\",",
+                "\"unclosed\":[1],\"completion\":\")\"}]"
+            ),
+            lines.diagnostics_json(&Checker::default())
+        );
+        assert_eq!("\"a\\\"b\\\\\\n\"", json_string("a\"b\\\n"));
+    }
+
+    #[test]
+    fn test_stream() {
+        let checker = Checker::default();
+        let input = "[(\n)>\n{<é>".as_bytes();
+        // Feeding one byte at a time splits lines and the two-byte é.
+        let mut stream = checker.stream();
+        let errors = input
+            .iter()
+            .flat_map(|b| stream.feed(std::slice::from_ref(b)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                StreamError::Mismatched {
+                    at: Position { line: 2, column: 2 },
+                    found: '>',
+                    expected: Some(']'),
+                    opened_at: Some(Position { line: 1, column: 1 }),
+                },
+                StreamError::Unknown {
+                    at: Position { line: 3, column: 3 },
+                    found: 'é',
+                },
+            ],
+            errors
+        );
+        let end = stream.finish();
+        assert_eq!("}]", end.completion);
+        assert_eq!(
+            vec![
+                (Position { line: 1, column: 1 }, '['),
+                (Position { line: 3, column: 1 }, '{'),
+            ],
+            end.unclosed
+        );
+    }
+
+    #[test]
+    fn test_check_reader() {
+        let mut errors = Vec::new();
+        let end = Checker::default()
+            .check_reader(&b"(\xff\n[\n"[..], |e| errors.push(e))
+            .unwrap();
+        assert_eq!(
+            vec!["1:2: unknown char '\u{fffd}'".to_string()],
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>()
+        );
+        assert_eq!("])", end.completion);
+        assert!(end.errors.is_empty());
+    }
+
+    #[test]
+    fn test_custom_scoring() {
+        let scoring = Scoring::new(
+            Checker::new(&[('(', ')'), ('[', ']')]).unwrap(),
+            &[(')', 1), (']', 10)],
+            &[(')', 1), (']', 2)],
+            3,
+        )
+        .unwrap();
+        let input = "(]\n[)\n(()\n[[(\n\n";
+        let lines = Lines::parse(testutil::string_reader(input)).unwrap();
+        assert_eq!(11, lines.syntax_error_score_with(&scoring));
+        assert_eq!(vec![1, 17], lines.completion_scores::<u64>(&scoring));
+        assert_eq!(Some(1), lines.middle_completion_score_with::<u64>(&scoring));
+        assert!(Scoring::new(Checker::default(), &[], &[], 5).is_err());
+        for (illegal, completion) in [
+            (
+                vec![(')', 1), (']', 10), ('}', 100)],
+                vec![(')', 1), (']', 2)],
+            ),
+            (
+                vec![(')', 1), (']', 10)],
+                vec![(')', 1), (']', 2), (']', 3)],
+            ),
+        ] {
+            let checker = Checker::new(&[('(', ')'), ('[', ']')]).unwrap();
+            assert!(Scoring::new(checker, &illegal, &completion, 3).is_err());
+        }
+
+        // Unknown chars are skipped, whether before a mismatched char or in a
+        // line that is only incomplete.
+        let input = "x(]\n(x(\n";
+        let lines = Lines::parse(testutil::string_reader(input)).unwrap();
+        assert_eq!(10, lines.syntax_error_score_with(&scoring));
+        assert_eq!(vec![4], lines.completion_scores::<u64>(&scoring));
+
+        let lines = Lines::parse(testutil::string_reader("()\n")).unwrap();
+        assert_eq!(None, lines.middle_completion_score_with::<u64>(&scoring));
+    }
+
+    #[test]
+    fn test_deep_completion_score() {
+        let lines = Lines::parse(testutil::string_reader(&"<".repeat(100))).unwrap();
+        let big = lines.completion_scores::<BigUint>(&Scoring::default());
+        assert_eq!(vec![BigUint::from(5u32).pow(100) - 1u32], big);
+        let modular = lines.completion_scores::<Modular<1_000_000_007>>(&Scoring::default());
+        assert_eq!(
+            (&big[0] % 1_000_000_007u32).to_string(),
+            modular[0].to_string()
+        );
     }
 }